@@ -0,0 +1,159 @@
+//! Modules compiled ahead of time, so a guest can start without compiling.
+//!
+//! An artifact holds a header describing how its code was generated, the original
+//! WebAssembly (for profile detection and symbolized backtraces) and the output of
+//! `Module::serialize`. The header must match the host exactly before the code is
+//! loaded, since deserializing code from another compiler or target is unsound.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use wasmer::{Engine, Features, Triple};
+
+use crate::{
+    compiler::Backend,
+    error::{Error, Result},
+    features::enabled_features,
+};
+
+const MAGIC: &[u8; 8] = b"CSGUESTA";
+
+/// Bumped whenever the layout after the magic changes.
+const FORMAT_VERSION: u32 = 1;
+
+/// How an artifact's code was generated.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtifactHeader {
+    pub format: u32,
+    pub engine: String,
+    pub compiler: String,
+    pub features: Vec<String>,
+    pub target: String,
+    /// Limits and metering the code was generated for, as in the module cache key.
+    pub variant: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    pub header: ArtifactHeader,
+    pub wasm: Vec<u8>,
+    pub compiled: Vec<u8>,
+}
+
+impl ArtifactHeader {
+    /// The header of code generated by `engine` on this host.
+    pub fn new(engine: &Engine, backend: Backend, features: &Features, variant: String) -> Self {
+        ArtifactHeader {
+            format: FORMAT_VERSION,
+            engine: engine.deterministic_id().to_string(),
+            compiler: backend.name().to_string(),
+            features: enabled_features(features)
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            target: Triple::host().to_string(),
+            variant,
+        }
+    }
+
+    /// Refuses an artifact whose code wasn't generated the way `host` would.
+    pub fn check(&self, host: &ArtifactHeader) -> Result<()> {
+        let fields = [
+            ("engine", &self.engine, &host.engine),
+            ("compiler", &self.compiler, &host.compiler),
+            ("target", &self.target, &host.target),
+            ("code variant", &self.variant, &host.variant),
+        ];
+        for (field, artifact, expected) in fields {
+            if artifact != expected {
+                return Err(Error::ArtifactMismatch {
+                    field,
+                    artifact: artifact.clone(),
+                    host: expected.clone(),
+                });
+            }
+        }
+        if self.features != host.features {
+            return Err(Error::ArtifactMismatch {
+                field: "features",
+                artifact: self.features.join(", "),
+                host: host.features.join(", "),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ArtifactHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}) for {}", self.compiler, self.engine, self.target)?;
+        if !self.features.is_empty() {
+            write!(f, ", features: {}", self.features.join(", "))?;
+        }
+        if !self.variant.is_empty() {
+            write!(f, ", variant: {}", self.variant)?;
+        }
+        Ok(())
+    }
+}
+
+impl Artifact {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let Some(rest) = bytes.strip_prefix(MAGIC) else {
+            return Err(Error::InvalidArtifact(
+                "the file is not an artifact made by `compile`".to_string(),
+            ));
+        };
+        let mut reader = Reader(rest);
+
+        let format = u32::from_le_bytes(reader.array()?);
+        if format != FORMAT_VERSION {
+            return Err(Error::InvalidArtifact(format!(
+                "format version {format} is not supported, expected {FORMAT_VERSION}"
+            )));
+        }
+        let length = u32::from_le_bytes(reader.array()?);
+        let header = serde_json::from_slice(reader.take(length as usize)?)
+            .map_err(|err| Error::InvalidArtifact(format!("unreadable header: {err}")))?;
+        let length = u64::from_le_bytes(reader.array()?);
+        let wasm = reader.take(length as usize)?.to_vec();
+
+        Ok(Artifact {
+            header,
+            wasm,
+            compiled: reader.0.to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header = serde_json::to_vec(&self.header).expect("the header is always serializable");
+        let mut bytes = Vec::with_capacity(
+            MAGIC.len() + 16 + header.len() + self.wasm.len() + self.compiled.len(),
+        );
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&header);
+        bytes.extend_from_slice(&(self.wasm.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&self.wasm);
+        bytes.extend_from_slice(&self.compiled);
+        bytes
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(Error::InvalidArtifact("the file is truncated".to_string()));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("took exactly N bytes"))
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use wasmer::{Engine, Features, Module, Store};
+use wasmer_cache::{Cache, FileSystemCache, Hash};
+
+use crate::{
+    compiler::feature_flags,
+    error::{Error, Result},
+};
+
+const CACHE_EXTENSION: &str = "wasmu";
+
+pub const DEFAULT_CACHE_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// On-disk cache of compiled modules, keyed by module bytes, engine, features and a
+/// variant describing anything else that changes code generation.
+pub struct ModuleCache {
+    dir: PathBuf,
+    max_size: u64,
+    inner: FileSystemCache,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    Hit,
+    Miss,
+}
+
+impl ModuleCache {
+    pub fn open(dir: impl Into<PathBuf>, max_size: u64) -> Result<Self> {
+        let dir = dir.into();
+        let mut inner =
+            FileSystemCache::new(&dir).map_err(Error::file("open the module cache at", &dir))?;
+        inner.set_cache_extension(Some(CACHE_EXTENSION));
+        Ok(Self {
+            dir,
+            max_size,
+            inner,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn key(engine: &Engine, features: &Features, variant: &str, bytes: &[u8]) -> Hash {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(bytes);
+        hasher.update(engine.deterministic_id().as_bytes());
+        // Each flag by name, so the key doesn't hinge on how `Features` prints.
+        let mut features = features.clone();
+        for (name, enabled) in feature_flags(&mut features) {
+            hasher.update(name.as_bytes());
+            hasher.update(&[u8::from(*enabled)]);
+        }
+        hasher.update(variant.as_bytes());
+        Hash::new(*hasher.finalize().as_bytes())
+    }
+
+    /// Loads the module from the cache, compiling and storing it on a miss.
+    pub fn load_or_compile(
+        &mut self,
+        store: &Store,
+        features: &Features,
+        variant: &str,
+        bytes: &[u8],
+    ) -> Result<(Module, CacheStatus)> {
+        let key = Self::key(store.engine(), features, variant, bytes);
+
+        // Safety: the cache directory is only ever written by `store` below, so the
+        // artifacts in it were produced by a compatible engine.
+        if let Ok(module) = unsafe { self.inner.load(store, key) } {
+            self.touch(key);
+            return Ok((module, CacheStatus::Hit));
+        }
+
+        let module = Module::new(store, bytes)?;
+        if let Err(err) = self.inner.store(key, &module) {
+            log::warn!("Failed to write module {key} to the cache: {err}");
+        } else if let Err(err) = self.evict() {
+            log::warn!("Failed to evict old cache entries: {err}");
+        }
+        Ok((module, CacheStatus::Miss))
+    }
+
+    fn entry_path(&self, key: Hash) -> PathBuf {
+        self.dir.join(format!("{key}.{CACHE_EXTENSION}"))
+    }
+
+    fn touch(&self, key: Hash) {
+        if let Ok(file) = fs::File::options().write(true).open(self.entry_path(key)) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+    }
+
+    fn entries(&self) -> Result<Vec<(PathBuf, u64, SystemTime)>> {
+        let mut entries = Vec::new();
+        let dir = fs::read_dir(&self.dir).map_err(Error::file("read", &self.dir))?;
+        for entry in dir {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(CACHE_EXTENSION) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            entries.push((path, metadata.len(), modified));
+        }
+        Ok(entries)
+    }
+
+    /// Removes the least recently used entries until the cache fits in `max_size`.
+    pub fn evict(&self) -> Result<usize> {
+        let mut entries = self.entries()?;
+        entries.sort_by_key(|(_, _, modified)| *modified);
+
+        let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        let mut removed = 0;
+        for (path, size, _) in entries {
+            if total <= self.max_size {
+                break;
+            }
+            fs::remove_file(&path)?;
+            total -= size;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    /// Removes every cached module, returning how many were removed and their size.
+    pub fn clean(&self) -> Result<(usize, u64)> {
+        let entries = self.entries()?;
+        let mut freed = 0;
+        for (path, size, _) in &entries {
+            fs::remove_file(path)?;
+            freed += size;
+        }
+        Ok((entries.len(), freed))
+    }
+}
+
+pub fn default_cache_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("CS_RUNTIME_CACHE_DIR") {
+        return PathBuf::from(dir);
+    }
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir);
+    base.join("cs-runtime-example")
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmer::Store;
+
+    use super::*;
+
+    #[test]
+    fn every_feature_flag_changes_the_key() {
+        let engine = Store::default().engine().clone();
+        let base = Features::default();
+        let key = |features: &Features| ModuleCache::key(&engine, features, "", b"module");
+
+        let mut keys = vec![key(&base)];
+        for index in 0..feature_flags(&mut base.clone()).len() {
+            let mut features = base.clone();
+            let flags = feature_flags(&mut features);
+            *flags[index].1 = !*flags[index].1;
+            keys.push(key(&features));
+        }
+        let count = keys.len();
+        keys.sort_by_key(|hash| hash.to_string());
+        keys.dedup();
+        assert_eq!(keys.len(), count);
+    }
+}
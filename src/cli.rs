@@ -0,0 +1,418 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::{builder::PossibleValuesParser, Args, Parser, Subcommand};
+use eyre::{eyre, Result};
+use url::Url;
+
+use cs_runtime_example::{
+    cache::DEFAULT_CACHE_MAX_SIZE,
+    compiler::Backend,
+    features::{feature_names, FeatureOverrides},
+    http::HttpMode,
+    mounts::{guest_path, parse_mapdir, parse_memdir, Mount},
+    profile::ProfileChoice,
+    stdin::StdinSource,
+    stdio::StreamOptions,
+    tunables::ResourceLimits,
+    DEFAULT_PROGRAM_NAME,
+};
+
+#[derive(Debug, Parser)]
+#[command(name = "cs-runtime-example", about = "Run C# WASI guests on wasmer")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run a guest module's `_start` export
+    Run(RunArgs),
+    /// Initialize a guest module, call one of its exports with arguments and print the results
+    Invoke(InvokeArgs),
+    /// Initialize a library module once, then call exports read from stdin, one
+    /// `EXPORT ARGS...` per line
+    Reactor(ReactorArgs),
+    /// Describe a module and the imports the host can't satisfy, without running it
+    Inspect(InspectArgs),
+    /// Compile a module ahead of time into an artifact `run --precompiled` starts without compiling
+    Compile(CompileArgs),
+    /// Manage the compiled module cache
+    #[command(subcommand)]
+    Cache(CacheCommand),
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct InspectArgs {
+    /// Guest module to inspect
+    pub module: PathBuf,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    pub json: bool,
+
+    /// Compiler used to validate the module
+    #[arg(long, value_enum, default_value_t = Backend::Cranelift)]
+    pub backend: Backend,
+
+    #[command(flatten)]
+    pub features: FeatureArgs,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct CompileArgs {
+    /// Guest module to compile
+    pub module: PathBuf,
+
+    /// Where to write the artifact
+    #[arg(short, long, value_name = "FILE")]
+    pub output: PathBuf,
+
+    /// Compiler used to translate the module to native code
+    #[arg(long, value_enum, default_value_t = Backend::default())]
+    pub backend: Backend,
+
+    #[command(flatten)]
+    pub features: FeatureArgs,
+
+    #[command(flatten)]
+    pub profile: ProfileArgs,
+
+    // Runs of the artifact must use the same limits, fuel metering and timeout checks.
+    #[command(flatten)]
+    pub limits: LimitArgs,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct FeatureArgs {
+    /// Enable a WebAssembly proposal the module doesn't appear to use
+    #[arg(long, value_name = "FEATURE", value_parser = PossibleValuesParser::new(feature_names()))]
+    pub enable_feature: Vec<String>,
+
+    /// Disable a WebAssembly proposal detected in the module
+    #[arg(long, value_name = "FEATURE", value_parser = PossibleValuesParser::new(feature_names()))]
+    pub disable_feature: Vec<String>,
+}
+
+impl FeatureArgs {
+    pub fn overrides(&self) -> FeatureOverrides {
+        FeatureOverrides {
+            enable: self.enable_feature.clone(),
+            disable: self.disable_feature.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ProfileArgs {
+    /// Runtime-specific settings to apply, detected from the module by default
+    #[arg(
+        long = "profile",
+        value_name = "PROFILE",
+        value_enum,
+        default_value_t = ProfileChoice::Auto
+    )]
+    pub choice: ProfileChoice,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CacheCommand {
+    /// Remove every cached module
+    Clean(CacheOptions),
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct CacheOptions {
+    /// Directory holding compiled modules
+    #[arg(long, value_name = "DIR")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Evict the least recently used modules once the cache grows past this many bytes
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_CACHE_MAX_SIZE)]
+    pub cache_max_size: u64,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct HttpArgs {
+    /// Send guest HTTP requests to a stand-in server and record the exchanges to this file
+    #[arg(long, value_name = "FILE", requires = "http_upstream")]
+    pub http_record: Option<PathBuf>,
+
+    /// Base URL of the stand-in server used while recording, e.g. `http://127.0.0.1:8080`
+    #[arg(long, value_name = "URL", requires = "http_record")]
+    pub http_upstream: Option<Url>,
+
+    /// Answer guest HTTP requests from a recording, without any network
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["http_record", "http_stub"])]
+    pub http_replay: Option<PathBuf>,
+
+    /// Answer guest HTTP requests from a TOML or JSON rules file
+    #[arg(long, value_name = "FILE", conflicts_with = "http_record")]
+    pub http_stub: Option<PathBuf>,
+}
+
+impl HttpArgs {
+    pub fn mode(&self) -> Option<HttpMode> {
+        if let (Some(path), Some(upstream)) = (&self.http_record, &self.http_upstream) {
+            return Some(HttpMode::Record {
+                path: path.clone(),
+                upstream: upstream.clone(),
+            });
+        }
+        self.http_replay
+            .clone()
+            .map(HttpMode::Replay)
+            .or_else(|| self.http_stub.clone().map(HttpMode::Stub))
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct StdioArgs {
+    /// Text written before every line of guest stdout
+    #[arg(long, value_name = "TEXT")]
+    pub stdout_prefix: Option<String>,
+
+    /// Text written before every line of guest stderr
+    #[arg(long, value_name = "TEXT")]
+    pub stderr_prefix: Option<String>,
+
+    /// Prefix guest output lines with the time since the guest started
+    #[arg(long)]
+    pub timestamps: bool,
+
+    /// Also write the guest's raw stdout to this file
+    #[arg(long, value_name = "FILE")]
+    pub tee_stdout: Option<PathBuf>,
+
+    /// Also write the guest's raw stderr to this file
+    #[arg(long, value_name = "FILE")]
+    pub tee_stderr: Option<PathBuf>,
+}
+
+impl StdioArgs {
+    pub fn stdout(&self) -> StreamOptions {
+        StreamOptions {
+            prefix: self.stdout_prefix.clone(),
+            timestamps: self.timestamps,
+            tee: self.tee_stdout.clone(),
+        }
+    }
+
+    pub fn stderr(&self) -> StreamOptions {
+        StreamOptions {
+            prefix: self.stderr_prefix.clone(),
+            timestamps: self.timestamps,
+            tee: self.tee_stderr.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct FsArgs {
+    /// Mount a host directory into the guest, as GUEST:HOST[:ro|:rw]
+    #[arg(long, value_name = "GUEST:HOST[:MODE]", value_parser = parse_mapdir)]
+    pub mapdir: Vec<Mount>,
+
+    /// Mount an in-memory directory, as GUEST[:SEED] where SEED is a directory or tarball
+    #[arg(long, value_name = "GUEST[:SEED]", value_parser = parse_memdir)]
+    pub memdir: Vec<Mount>,
+
+    /// Mount --mapdir directories as in-memory snapshots (up to 256 MiB each) so guest writes never touch the host
+    #[arg(long)]
+    pub overlay: bool,
+}
+
+impl FsArgs {
+    pub fn mounts(&self) -> Vec<Mount> {
+        self.mapdir.iter().chain(&self.memdir).cloned().collect()
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct LimitArgs {
+    /// Stop the guest after it executes this many WebAssembly operators
+    #[arg(long, value_name = "UNITS")]
+    pub fuel: Option<u64>,
+
+    /// Stop the guest after it has run this long, e.g. `500ms`, `30s` or `2m`
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub timeout: Option<Duration>,
+
+    /// Largest size each linear memory may grow to, in 64 KiB pages
+    #[arg(long, value_name = "PAGES")]
+    pub max_memory_pages: Option<u32>,
+
+    /// Largest number of elements each table may grow to
+    #[arg(long, value_name = "ELEMENTS")]
+    pub max_table_elements: Option<u32>,
+
+    /// Largest number of linear memories the module may declare
+    #[arg(long, value_name = "COUNT")]
+    pub max_memories: Option<u32>,
+}
+
+impl LimitArgs {
+    pub fn resources(&self) -> ResourceLimits {
+        ResourceLimits {
+            memory_pages: self.max_memory_pages,
+            table_elements: self.max_table_elements,
+            memories: self.max_memories,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Guest module to run, or an artifact with `--precompiled`; defaults to the bundled cswasi.wasm
+    pub module: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub guest: GuestArgs,
+
+    /// Arguments passed to the guest
+    #[arg(last = true)]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct InvokeArgs {
+    /// Export to call
+    pub export: String,
+
+    /// Arguments for the export, parsed according to its signature
+    #[arg(allow_hyphen_values = true)]
+    pub values: Vec<String>,
+
+    /// Guest module, defaults to the bundled cswasi.wasm
+    #[arg(long, value_name = "FILE")]
+    pub module: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub guest: GuestArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ReactorArgs {
+    /// Guest module, built as a library
+    pub module: PathBuf,
+
+    #[command(flatten)]
+    pub guest: GuestArgs,
+}
+
+/// How the guest is compiled and sandboxed, shared by `run`, `invoke` and `reactor`.
+#[derive(Debug, Args)]
+pub struct GuestArgs {
+    /// Environment variable passed to the guest, as KEY=VALUE
+    #[arg(short, long = "env", value_name = "KEY=VALUE", value_parser = parse_env_var)]
+    pub envs: Vec<(String, String)>,
+
+    /// Working directory of the guest, a path in its filesystem
+    #[arg(long, value_name = "DIR", value_parser = guest_path)]
+    pub cwd: Option<PathBuf>,
+
+    /// Compiler used to translate the module to native code; artifacts use the one they were compiled with
+    #[arg(long, value_enum, default_value_t = Backend::default())]
+    pub backend: Backend,
+
+    /// Fail instead of retrying with Cranelift when the chosen backend can't compile the module
+    #[arg(long)]
+    pub no_fallback: bool,
+
+    #[command(flatten)]
+    pub features: FeatureArgs,
+
+    #[command(flatten)]
+    pub cache: CacheOptions,
+
+    #[command(flatten)]
+    pub fs: FsArgs,
+
+    #[command(flatten)]
+    pub limits: LimitArgs,
+
+    /// Capability policy for the guest, defaults to `<module>.policy.toml` or `.json`
+    #[arg(long, value_name = "FILE")]
+    pub policy: Option<PathBuf>,
+
+    /// Run without a policy, giving the guest every capability, even if one sits next to the module
+    #[arg(long, conflicts_with = "policy")]
+    pub insecure_allow_all: bool,
+
+    #[command(flatten)]
+    pub http: HttpArgs,
+
+    /// Give the guest an in-process loopback network, host interfaces are never reachable
+    #[arg(long)]
+    pub loopback_net: bool,
+
+    /// Replace imports the host doesn't implement with functions that trap when called
+    #[arg(long)]
+    pub stub_missing_imports: bool,
+
+    /// Guest stdin: `closed`, `inherit`, `file:<path>` or `text:<string>`
+    #[arg(long, value_name = "SOURCE", default_value_t = StdinSource::Closed)]
+    pub stdin: StdinSource,
+
+    #[command(flatten)]
+    pub stdio: StdioArgs,
+
+    /// Always compile the module instead of using the cache
+    #[arg(long)]
+    pub no_cache: bool,
+
+    /// The module is an artifact made by `compile`; its native code is trusted as is
+    #[arg(long)]
+    pub precompiled: bool,
+
+    /// Program name the guest sees as argv[0]
+    #[arg(long, default_value = DEFAULT_PROGRAM_NAME)]
+    pub program_name: String,
+
+    #[command(flatten)]
+    pub profile: ProfileArgs,
+}
+
+fn parse_env_var(s: &str) -> Result<(String, String)> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| eyre!("expected KEY=VALUE, got `{s}`"))?;
+    if key.is_empty() {
+        return Err(eyre!("environment variable name is empty in `{s}`"));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+fn parse_duration(s: &str) -> Result<Duration> {
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value.parse().map_err(|_| eyre!("invalid duration `{s}`"))?;
+    let seconds = match unit {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => {
+            return Err(eyre!(
+                "unknown unit `{unit}` in `{s}`, expected ms, s, m or h"
+            ))
+        }
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| eyre!("duration `{s}` is out of range"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("1.5").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+        assert!(parse_duration("3d").is_err());
+        assert!(parse_duration("99999999999999999999h").is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use std::{fmt, sync::Arc};
+
+use wasmer::{CompilerConfig, Engine, EngineBuilder, Features, ModuleMiddleware};
+use wasmer_compiler_cranelift::Cranelift;
+use wasmer_compiler_singlepass::Singlepass;
+
+const CRANELIFT_UNSUPPORTED: &[&str] = &["exceptions"];
+
+const SINGLEPASS_UNSUPPORTED: &[&str] = &[
+    "simd",
+    "relaxed_simd",
+    "reference_types",
+    "multi_value",
+    "multi_memory",
+    "module_linking",
+    "tail_call",
+    "memory64",
+    "exceptions",
+    "extended_const",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Backend {
+    /// Optimizing compiler, slower to compile but produces faster code
+    #[default]
+    Cranelift,
+    /// Linear-time compiler for fast startup, supports fewer proposals
+    Singlepass,
+}
+
+impl Backend {
+    pub const ALL: [Backend; 2] = [Backend::Cranelift, Backend::Singlepass];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Cranelift => "cranelift",
+            Backend::Singlepass => "singlepass",
+        }
+    }
+
+    /// The backend called `name`, as written by [`Backend::name`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        Backend::ALL
+            .into_iter()
+            .find(|backend| backend.name() == name)
+    }
+
+    fn lacks(self, feature: &str) -> bool {
+        match self {
+            Backend::Cranelift => CRANELIFT_UNSUPPORTED.contains(&feature),
+            Backend::Singlepass => SINGLEPASS_UNSUPPORTED.contains(&feature),
+        }
+    }
+
+    /// Names of the enabled proposals in `features` that this backend can't compile.
+    pub fn unsupported_features(self, features: &Features) -> Vec<&'static str> {
+        let mut features = features.clone();
+        feature_flags(&mut features)
+            .into_iter()
+            .filter(|(name, enabled)| **enabled && self.lacks(name))
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    pub fn engine(self, features: &Features, middlewares: &[Arc<dyn ModuleMiddleware>]) -> Engine {
+        match self {
+            Backend::Cranelift => {
+                let mut compiler = Cranelift::default();
+                for middleware in middlewares {
+                    compiler.push_middleware(middleware.clone());
+                }
+                EngineBuilder::new(compiler)
+                    .set_features(Some(features.clone()))
+                    .engine()
+                    .into()
+            }
+            Backend::Singlepass => {
+                let mut compiler = Singlepass::default();
+                for middleware in middlewares {
+                    compiler.push_middleware(middleware.clone());
+                }
+                EngineBuilder::new(compiler)
+                    .set_features(Some(features.clone()))
+                    .engine()
+                    .into()
+            }
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+pub fn feature_flags(features: &mut Features) -> [(&'static str, &mut bool); 12] {
+    [
+        ("threads", &mut features.threads),
+        ("reference_types", &mut features.reference_types),
+        ("simd", &mut features.simd),
+        ("bulk_memory", &mut features.bulk_memory),
+        ("multi_value", &mut features.multi_value),
+        ("tail_call", &mut features.tail_call),
+        ("module_linking", &mut features.module_linking),
+        ("multi_memory", &mut features.multi_memory),
+        ("memory64", &mut features.memory64),
+        ("exceptions", &mut features.exceptions),
+        ("relaxed_simd", &mut features.relaxed_simd),
+        ("extended_const", &mut features.extended_const),
+    ]
+}
@@ -0,0 +1,233 @@
+use std::{collections::HashMap, fmt};
+
+use gimli::{ColumnType, Dwarf, EndianSlice, LittleEndian, SectionId};
+
+/// A file and line from the module's `.debug_line` program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: u64,
+    pub column: Option<u64>,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)?;
+        if let Some(column) = self.column {
+            write!(f, ":{column}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Row {
+    address: u64,
+    file: usize,
+    line: u64,
+    column: Option<u64>,
+}
+
+/// A contiguous run of rows ending at `end`, as emitted by one line sequence.
+#[derive(Debug)]
+struct Sequence {
+    start: u64,
+    end: u64,
+    rows: Vec<Row>,
+}
+
+/// Maps code-section offsets to source lines.
+#[derive(Debug, Default)]
+pub struct LineTable {
+    files: Vec<String>,
+    sequences: Vec<Sequence>,
+}
+
+impl LineTable {
+    /// Builds the table from the module's `.debug_*` custom sections, keyed by name.
+    ///
+    /// Returns `Ok(None)` when the module carries no line information.
+    pub fn parse(sections: &HashMap<&str, &[u8]>) -> Result<Option<Self>, gimli::Error> {
+        if !sections.contains_key(".debug_line") {
+            return Ok(None);
+        }
+
+        let load = |id: SectionId| -> Result<EndianSlice<'_, LittleEndian>, gimli::Error> {
+            let data = sections.get(id.name()).copied().unwrap_or(&[]);
+            Ok(EndianSlice::new(data, LittleEndian))
+        };
+        let dwarf = Dwarf::load(load)?;
+
+        let mut table = LineTable::default();
+        let mut file_ids: HashMap<String, usize> = HashMap::new();
+
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let Some(program) = unit.line_program.clone() else {
+                continue;
+            };
+
+            let mut rows = program.rows();
+            let mut current: Vec<Row> = Vec::new();
+            while let Some((header, row)) = rows.next_row()? {
+                if row.end_sequence() {
+                    if let Some(first) = current.first() {
+                        table.sequences.push(Sequence {
+                            start: first.address,
+                            end: row.address(),
+                            rows: std::mem::take(&mut current),
+                        });
+                    }
+                    continue;
+                }
+
+                let Some(line) = row.line() else {
+                    continue;
+                };
+                let file = match row.file(header) {
+                    Some(entry) => {
+                        let name = dwarf.attr_string(&unit, entry.path_name())?;
+                        let dir = match entry.directory(header) {
+                            Some(dir) => Some(dwarf.attr_string(&unit, dir)?),
+                            None => unit.comp_dir,
+                        };
+                        match dir {
+                            Some(dir) => join_path(&dir.to_string_lossy(), &name.to_string_lossy()),
+                            None => name.to_string_lossy().into_owned(),
+                        }
+                    }
+                    None => "<unknown>".to_string(),
+                };
+                let file = *file_ids.entry(file.clone()).or_insert_with(|| {
+                    table.files.push(file);
+                    table.files.len() - 1
+                });
+
+                current.push(Row {
+                    address: row.address(),
+                    file,
+                    line: line.get(),
+                    column: match row.column() {
+                        ColumnType::LeftEdge => None,
+                        ColumnType::Column(column) => Some(column.get()),
+                    },
+                });
+            }
+        }
+
+        table.sequences.sort_by_key(|s| s.start);
+        Ok(Some(table))
+    }
+
+    /// Looks up an offset relative to the start of the code section.
+    pub fn lookup(&self, address: u64) -> Option<SourceLocation> {
+        let index = self
+            .sequences
+            .partition_point(|s| s.start <= address)
+            .checked_sub(1)?;
+        let sequence = &self.sequences[index];
+        if address >= sequence.end {
+            return None;
+        }
+
+        let row = sequence.rows.partition_point(|r| r.address <= address);
+        let row = sequence.rows[row.checked_sub(1)?];
+        Some(SourceLocation {
+            file: self.files[row.file].clone(),
+            line: row.line,
+            column: row.column,
+        })
+    }
+}
+
+/// Joins a DWARF directory and file name. The guests are often built on Windows,
+/// so this follows the directory's separator rather than the host's.
+fn join_path(dir: &str, name: &str) -> String {
+    let is_absolute = name.starts_with(['/', '\\']) || name.as_bytes().get(1) == Some(&b':');
+    if dir.is_empty() || is_absolute {
+        return name.to_string();
+    }
+    let separator = if dir.contains('\\') && !dir.contains('/') {
+        '\\'
+    } else {
+        '/'
+    };
+    let dir = dir.trim_end_matches(['/', '\\']);
+    format!("{dir}{separator}{name}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(address: u64, file: usize, line: u64) -> Row {
+        Row {
+            address,
+            file,
+            line,
+            column: None,
+        }
+    }
+
+    /// Two sequences with a gap between them, like two compilation units.
+    fn table() -> LineTable {
+        LineTable {
+            files: vec!["Program.cs".to_string(), "Helpers.cs".to_string()],
+            sequences: vec![
+                Sequence {
+                    start: 0x10,
+                    end: 0x40,
+                    rows: vec![row(0x10, 0, 5), row(0x20, 0, 6), row(0x30, 0, 8)],
+                },
+                Sequence {
+                    start: 0x80,
+                    end: 0x90,
+                    rows: vec![Row {
+                        column: Some(13),
+                        ..row(0x80, 1, 42)
+                    }],
+                },
+            ],
+        }
+    }
+
+    fn location(file: &str, line: u64, column: Option<u64>) -> Option<SourceLocation> {
+        Some(SourceLocation {
+            file: file.to_string(),
+            line,
+            column,
+        })
+    }
+
+    #[test]
+    fn finds_the_row_covering_an_address() {
+        let table = table();
+        assert_eq!(table.lookup(0x10), location("Program.cs", 5, None));
+        assert_eq!(table.lookup(0x1f), location("Program.cs", 5, None));
+        assert_eq!(table.lookup(0x20), location("Program.cs", 6, None));
+        assert_eq!(table.lookup(0x3f), location("Program.cs", 8, None));
+        assert_eq!(table.lookup(0x85), location("Helpers.cs", 42, Some(13)));
+    }
+
+    #[test]
+    fn misses_addresses_outside_every_sequence() {
+        let table = table();
+        assert_eq!(table.lookup(0x0), None);
+        assert_eq!(table.lookup(0x40), None);
+        assert_eq!(table.lookup(0x50), None);
+        assert_eq!(table.lookup(0x90), None);
+        assert_eq!(LineTable::default().lookup(0x10), None);
+    }
+
+    #[test]
+    fn joins_paths_with_the_directory_separator() {
+        assert_eq!(join_path("/src/app", "Program.cs"), "/src/app/Program.cs");
+        assert_eq!(
+            join_path("C:\\src\\app\\", "Program.cs"),
+            "C:\\src\\app\\Program.cs"
+        );
+        assert_eq!(join_path("/src/app", "/abs/Program.cs"), "/abs/Program.cs");
+        assert_eq!(join_path("", "Program.cs"), "Program.cs");
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use virtual_fs::FsError;
+use wasmer::{CompileError, DeserializeError, ExportError, InstantiationError, SerializeError};
+use wasmer_wasix::{WasiError, WasiRuntimeError, WasiStateCreationError};
+
+use crate::{compiler::Backend, imports::ImportProblem, outcome::Outcome};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Everything that can go wrong while preparing or running a guest.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to {action} {}", path.display())]
+    File {
+        action: &'static str,
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// A policy, recording or rules file couldn't be parsed.
+    #[error("invalid {kind} {}", path.display())]
+    Config {
+        kind: &'static str,
+        path: PathBuf,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("{0}")]
+    InvalidArgument(String),
+    /// Building the guest's sandbox filesystem failed.
+    #[error("failed to {action} {} in the guest filesystem", path.display())]
+    Filesystem {
+        action: &'static str,
+        path: PathBuf,
+        #[source]
+        source: FsError,
+    },
+    #[error("invalid module")]
+    InvalidModule(#[from] wasmparser::BinaryReaderError),
+    /// The module asks for more than the resource limits allow.
+    #[error("{0}")]
+    Limit(String),
+    #[error("the host cannot satisfy the guest's imports:{}", list_problems(.0))]
+    UnsatisfiedImports(Vec<ImportProblem>),
+    #[error("failed to initialize the WAI bindings: {0}")]
+    Bindings(String),
+    /// The chosen compiler can't compile a proposal the module needs.
+    #[error("{backend} does not support {}, which the module needs", .features.join(", "))]
+    UnsupportedFeatures {
+        backend: Backend,
+        features: Vec<&'static str>,
+    },
+    #[error(transparent)]
+    Compile(#[from] CompileError),
+    #[error(transparent)]
+    Serialize(#[from] SerializeError),
+    #[error(transparent)]
+    Deserialize(#[from] DeserializeError),
+    #[error("invalid precompiled module: {0}")]
+    InvalidArtifact(String),
+    /// A precompiled module's code was generated differently than this host would.
+    #[error(
+        "the module was precompiled for {field} `{artifact}`, but this host uses `{host}`; \
+         compile it again"
+    )]
+    ArtifactMismatch {
+        field: &'static str,
+        artifact: String,
+        host: String,
+    },
+    #[error(transparent)]
+    Instantiation(Box<InstantiationError>),
+    #[error("the module has no export `{name}`{}", list_exports(.available))]
+    MissingExport {
+        name: String,
+        /// Signatures of the functions the module does export.
+        available: Vec<String>,
+        #[source]
+        source: ExportError,
+    },
+    #[error(transparent)]
+    Wasi(#[from] WasiError),
+    #[error(transparent)]
+    WasiRuntime(Box<WasiRuntimeError>),
+    #[error(transparent)]
+    WasiState(#[from] WasiStateCreationError),
+    /// The module lacks an export WASI needs, such as its memory.
+    #[error("the module can't be run with WASI")]
+    WasiExport(#[source] ExportError),
+    #[error(
+        "the module is a command, it exports `_start` but no `_initialize`; build it as a \
+         library to keep it alive between calls"
+    )]
+    NotAReactor,
+    #[error("guest output forwarder panicked")]
+    Forwarder(#[source] tokio::task::JoinError),
+    /// A call into the guest didn't return normally.
+    #[error("{0}")]
+    Guest(Outcome),
+}
+
+impl Error {
+    /// Wraps an I/O error with the operation and the host path it was about.
+    pub(crate) fn file<'a>(
+        action: &'static str,
+        path: &'a Path,
+    ) -> impl FnOnce(io::Error) -> Self + 'a {
+        move |source| Error::File {
+            action,
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    /// Wraps a sandbox filesystem error with the operation and the guest path.
+    pub(crate) fn fs<'a>(
+        action: &'static str,
+        path: &'a Path,
+    ) -> impl FnOnce(FsError) -> Self + 'a {
+        move |source| Error::Filesystem {
+            action,
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub(crate) fn config<'a, E>(kind: &'static str, path: &'a Path) -> impl FnOnce(E) -> Self + 'a
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        move |source| Error::Config {
+            kind,
+            path: path.to_path_buf(),
+            source: Box::new(source),
+        }
+    }
+}
+
+impl From<InstantiationError> for Error {
+    fn from(err: InstantiationError) -> Self {
+        Error::Instantiation(Box::new(err))
+    }
+}
+
+impl From<WasiRuntimeError> for Error {
+    fn from(err: WasiRuntimeError) -> Self {
+        Error::WasiRuntime(Box::new(err))
+    }
+}
+
+fn list_problems(problems: &[ImportProblem]) -> String {
+    problems
+        .iter()
+        .map(|problem| format!("\n  {problem}"))
+        .collect()
+}
+
+fn list_exports(exports: &[String]) -> String {
+    match exports {
+        [] => ", it exports no functions".to_string(),
+        exports => format!(", callable exports:\n  {}", exports.join("\n  ")),
+    }
+}
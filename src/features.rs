@@ -0,0 +1,278 @@
+//! Works out which WebAssembly proposals a module needs, so the engine only enables
+//! those instead of every proposal the C# toolchains might use.
+
+use wasmer::Features;
+use wasmparser::{
+    BinaryReaderError, BlockType, Operator, Parser, Payload, TypeRef, ValType, Validator,
+    WasmFeatures,
+};
+
+use crate::{
+    compiler::feature_flags,
+    error::{Error, Result},
+    inspect::read_target_features,
+};
+
+/// `target_features` entries written by LLVM, and the proposal each one needs.
+/// Entries for proposals every engine supports, like `sign-ext`, are left out.
+const TARGET_FEATURES: &[(&str, &str)] = &[
+    ("atomics", "threads"),
+    ("shared-mem", "threads"),
+    ("bulk-memory", "bulk_memory"),
+    ("reference-types", "reference_types"),
+    ("simd128", "simd"),
+    ("relaxed-simd", "relaxed_simd"),
+    ("multivalue", "multi_value"),
+    ("tail-call", "tail_call"),
+    ("exception-handling", "exceptions"),
+    ("memory64", "memory64"),
+    ("multimemory", "multi_memory"),
+    ("extended-const", "extended_const"),
+];
+
+/// Names of the proposals that can be switched on and off.
+pub fn feature_names() -> Vec<&'static str> {
+    let mut features = Features::default();
+    feature_flags(&mut features)
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// Names of the proposals enabled in `features`.
+pub fn enabled_features(features: &Features) -> Vec<&'static str> {
+    let mut features = features.clone();
+    feature_flags(&mut features)
+        .into_iter()
+        .filter(|(_, enabled)| **enabled)
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// The smallest set of proposals the module needs, from its `target_features`
+/// section and the sections and instructions it contains, checked by validating
+/// the module with only those enabled.
+pub fn detect(bytes: &[u8]) -> Result<Features> {
+    let mut needed: Vec<&'static str> = Vec::new();
+    let mut memories = 0;
+    let mut tables = 0;
+
+    for payload in Parser::new(0).parse_all(bytes) {
+        match payload? {
+            Payload::CustomSection(section) if section.name() == "target_features" => {
+                for entry in read_target_features(section.data())? {
+                    // `-` entries are features the module was built without.
+                    let Some(name) = entry.strip_prefix(['+', '=']) else {
+                        continue;
+                    };
+                    if let Some((_, feature)) = TARGET_FEATURES.iter().find(|(n, _)| *n == name) {
+                        needed.push(*feature);
+                    }
+                }
+            }
+            Payload::TypeSection(reader) => {
+                for ty in reader {
+                    let wasmparser::Type::Func(ty) = ty?;
+                    if ty.results().len() > 1 {
+                        needed.push("multi_value");
+                    }
+                    if ty.params().iter().chain(ty.results()).any(is_v128) {
+                        needed.push("simd");
+                    }
+                }
+            }
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    match import?.ty {
+                        TypeRef::Memory(memory) => {
+                            memories += 1;
+                            needed.extend(memory_features(memory.shared, memory.memory64));
+                        }
+                        TypeRef::Table(table) => {
+                            tables += 1;
+                            if table.element_type == ValType::ExternRef {
+                                needed.push("reference_types");
+                            }
+                        }
+                        TypeRef::Tag(_) => needed.push("exceptions"),
+                        _ => {}
+                    }
+                }
+            }
+            Payload::MemorySection(reader) => {
+                for memory in reader {
+                    let memory = memory?;
+                    memories += 1;
+                    needed.extend(memory_features(memory.shared, memory.memory64));
+                }
+            }
+            Payload::TableSection(reader) => {
+                for table in reader {
+                    tables += 1;
+                    if table?.element_type == ValType::ExternRef {
+                        needed.push("reference_types");
+                    }
+                }
+            }
+            Payload::TagSection(_) => needed.push("exceptions"),
+            Payload::DataCountSection { .. } => needed.push("bulk_memory"),
+            Payload::CodeSectionEntry(body) => {
+                for local in body.get_locals_reader()? {
+                    if is_v128(&local?.1) {
+                        needed.push("simd");
+                    }
+                }
+                let mut reader = body.get_operators_reader()?;
+                while !reader.eof() {
+                    operator_features(&reader.read()?, &mut needed);
+                }
+            }
+            _ => {}
+        }
+    }
+    if memories > 1 {
+        needed.push("multi_memory");
+    }
+    if tables > 1 {
+        needed.push("reference_types");
+    }
+    // wasmparser only accepts reference types together with bulk memory.
+    if needed.contains(&"reference_types") {
+        needed.push("bulk_memory");
+    }
+
+    let mut features = Features::default();
+    for (name, enabled) in feature_flags(&mut features) {
+        *enabled = needed.contains(&name);
+    }
+
+    // Whatever the scan missed, e.g. a second memory used only by loads, shows up as
+    // a validation error. Enable everything the module validates with instead.
+    if let Err(err) = validate(bytes, &features) {
+        let detected = enabled_features(&features).join(", ");
+        for (name, enabled) in feature_flags(&mut features) {
+            *enabled = name != "module_linking";
+        }
+        validate(bytes, &features)?;
+        log::warn!(
+            "The module needs more than the detected features ({detected}): {err}; \
+             enabling all of them"
+        );
+    }
+    Ok(features)
+}
+
+fn is_v128(ty: &ValType) -> bool {
+    *ty == ValType::V128
+}
+
+fn memory_features(shared: bool, memory64: bool) -> impl Iterator<Item = &'static str> {
+    [(shared, "threads"), (memory64, "memory64")]
+        .into_iter()
+        .filter(|(used, _)| *used)
+        .map(|(_, feature)| feature)
+}
+
+macro_rules! define_operator_proposal {
+    ($( @$proposal:ident $op:ident $({ $($arg:ident: $argty:ty),* })? => $visit:ident)*) => {
+        /// The proposal that introduced an instruction, `None` for the MVP and the
+        /// proposals every engine has long supported.
+        fn operator_proposal(operator: &Operator) -> Option<&'static str> {
+            let proposal = match operator {
+                $(Operator::$op { .. } => stringify!($proposal),)*
+            };
+            match proposal {
+                "mvp" | "sign_extension" | "saturating_float_to_int" => None,
+                // The remaining proposal names are those of `wasmer::Features`.
+                proposal => Some(proposal),
+            }
+        }
+    };
+}
+
+wasmparser::for_each_operator!(define_operator_proposal);
+
+/// The proposals an instruction needs: the one that introduced it, and those that
+/// extended MVP instructions with new immediates or block types.
+fn operator_features(operator: &Operator, needed: &mut Vec<&'static str>) {
+    use Operator::*;
+
+    needed.extend(operator_proposal(operator));
+    match operator {
+        CallIndirect {
+            table_index,
+            table_byte,
+            ..
+        } if *table_index != 0 || *table_byte != 0 => needed.push("reference_types"),
+        MemorySize { mem, mem_byte } | MemoryGrow { mem, mem_byte }
+            if *mem != 0 || *mem_byte != 0 =>
+        {
+            needed.push("multi_memory")
+        }
+        Block { blockty } | Loop { blockty } | If { blockty } | Try { blockty } => match blockty {
+            BlockType::Empty => {}
+            BlockType::Type(ty) => needed.extend(value_type_feature(ty)),
+            // Blocks with parameters or several results refer to a function type.
+            BlockType::FuncType(_) => needed.push("multi_value"),
+        },
+        _ => {}
+    }
+}
+
+fn value_type_feature(ty: &ValType) -> Option<&'static str> {
+    match ty {
+        ValType::V128 => Some("simd"),
+        ValType::FuncRef | ValType::ExternRef => Some("reference_types"),
+        ValType::I32 | ValType::I64 | ValType::F32 | ValType::F64 => None,
+    }
+}
+
+/// Validates the module with only `features` enabled.
+fn validate(bytes: &[u8], features: &Features) -> Result<(), BinaryReaderError> {
+    let features = WasmFeatures {
+        threads: features.threads,
+        reference_types: features.reference_types,
+        simd: features.simd,
+        bulk_memory: features.bulk_memory,
+        multi_value: features.multi_value,
+        tail_call: features.tail_call,
+        multi_memory: features.multi_memory,
+        memory64: features.memory64,
+        exceptions: features.exceptions,
+        relaxed_simd: features.relaxed_simd,
+        extended_const: features.extended_const,
+        ..WasmFeatures::default()
+    };
+    Validator::new_with_features(features)
+        .validate_all(bytes)
+        .map(|_| ())
+}
+
+/// Proposals to force on or off on top of those detected in the module.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureOverrides {
+    pub enable: Vec<String>,
+    pub disable: Vec<String>,
+}
+
+impl FeatureOverrides {
+    pub fn apply(&self, features: &mut Features) -> Result<()> {
+        let overrides = self
+            .enable
+            .iter()
+            .map(|name| (name, true))
+            .chain(self.disable.iter().map(|name| (name, false)));
+        for (name, value) in overrides {
+            let mut flags = feature_flags(features);
+            let Some((_, enabled)) = flags.iter_mut().find(|(flag, _)| flag == &name.as_str())
+            else {
+                return Err(Error::InvalidArgument(format!(
+                    "unknown WebAssembly feature `{name}`, expected one of {}",
+                    feature_names().join(", ")
+                )));
+            };
+            **enabled = value;
+        }
+        Ok(())
+    }
+}
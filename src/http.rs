@@ -0,0 +1,531 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, bail, Context};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::future::BoxFuture;
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use url::Url;
+use wasmer_wasix::http::{HttpClient, HttpRequest, HttpResponse};
+
+use crate::{
+    error::{Error, Result},
+    policy::HttpPolicy,
+};
+
+/// Where the responses to guest HTTP requests come from.
+#[derive(Debug, Clone)]
+pub enum HttpMode {
+    /// Forward requests to a local stand-in server and save every exchange to a file.
+    Record { path: PathBuf, upstream: Url },
+    /// Answer requests from a file written in record mode, without any network.
+    Replay(PathBuf),
+    /// Answer requests from a TOML or JSON rules file.
+    Stub(PathBuf),
+}
+
+/// A request and the response it got, as stored in a recording.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exchange {
+    pub method: String,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Body::is_empty")]
+    pub request_body: Body,
+    pub status: u16,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    #[serde(default, skip_serializing_if = "Body::is_empty")]
+    pub body: Body,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Recording {
+    pub exchanges: Vec<Exchange>,
+}
+
+/// A message body, kept readable in the file when it is UTF-8.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Body {
+    #[default]
+    Empty,
+    Text(String),
+    Base64(String),
+}
+
+impl Body {
+    fn new(bytes: Option<Vec<u8>>) -> Self {
+        match bytes {
+            None => Body::Empty,
+            Some(bytes) if bytes.is_empty() => Body::Empty,
+            Some(bytes) => match String::from_utf8(bytes) {
+                Ok(text) => Body::Text(text),
+                Err(err) => Body::Base64(STANDARD.encode(err.into_bytes())),
+            },
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Body::Empty
+    }
+
+    fn bytes(&self) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(match self {
+            Body::Empty => None,
+            Body::Text(text) => Some(text.clone().into_bytes()),
+            Body::Base64(data) => Some(STANDARD.decode(data).context("invalid base64 body")?),
+        })
+    }
+}
+
+/// A canned response for requests matching `method` and `url`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StubRule {
+    /// Matches any method when unset.
+    pub method: Option<String>,
+    /// Exact URL, or a prefix when it ends with `*`.
+    pub url: String,
+    #[serde(default = "default_status")]
+    pub status: u16,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+    /// File holding the body, relative to the rules file. It may be binary.
+    pub body_file: Option<PathBuf>,
+    /// The body to answer with, from `body` or `body_file`.
+    #[serde(skip)]
+    contents: Option<Vec<u8>>,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StubRules {
+    rule: Vec<StubRule>,
+}
+
+impl StubRule {
+    fn matches(&self, method: &str, url: &str) -> bool {
+        let method_matches = self
+            .method
+            .as_deref()
+            .is_none_or(|m| m.eq_ignore_ascii_case(method));
+        let url_matches = match self.url.strip_suffix('*') {
+            Some(prefix) => url.starts_with(prefix),
+            None => url == self.url,
+        };
+        method_matches && url_matches
+    }
+}
+
+#[derive(Debug)]
+enum Backend {
+    Record {
+        path: PathBuf,
+        upstream: Url,
+        recording: Mutex<Recording>,
+    },
+    Replay {
+        exchanges: Vec<Exchange>,
+        used: Mutex<Vec<bool>>,
+    },
+    Stub(Vec<StubRule>),
+}
+
+/// The HTTP client guests' requests go through.
+#[derive(Debug)]
+pub struct HttpBackend {
+    backend: Backend,
+    allowed_hosts: Option<Vec<String>>,
+    allowed_methods: Vec<String>,
+}
+
+impl HttpBackend {
+    /// Opens the backend's files, restricting requests to what `policy` allows.
+    pub fn open(mode: HttpMode, policy: Option<&HttpPolicy>) -> Result<Self> {
+        let backend = match mode {
+            HttpMode::Record { path, upstream } => {
+                if upstream.scheme() != "http" {
+                    return Err(Error::InvalidArgument(format!(
+                        "the recording upstream must be an http:// URL, got {upstream}"
+                    )));
+                }
+                Backend::Record {
+                    path,
+                    upstream,
+                    recording: Mutex::new(Recording::default()),
+                }
+            }
+            HttpMode::Replay(path) => {
+                let json = fs::read(&path).map_err(Error::file("read recording", &path))?;
+                let recording: Recording =
+                    serde_json::from_slice(&json).map_err(Error::config("recording", &path))?;
+                let used = Mutex::new(vec![false; recording.exchanges.len()]);
+                Backend::Replay {
+                    exchanges: recording.exchanges,
+                    used,
+                }
+            }
+            HttpMode::Stub(path) => Backend::Stub(load_rules(&path)?),
+        };
+
+        let allowed_hosts = policy
+            .map(|policy| policy.allowed_hosts.clone())
+            .filter(|hosts| !hosts.iter().any(|host| host == "*"));
+        let allowed_methods = policy
+            .map(|policy| policy.allowed_methods.clone())
+            .unwrap_or_default();
+
+        Ok(HttpBackend {
+            backend,
+            allowed_hosts,
+            allowed_methods,
+        })
+    }
+
+    fn check_allowed(&self, request: &HttpRequest) -> anyhow::Result<()> {
+        if !self.allowed_methods.is_empty()
+            && !self
+                .allowed_methods
+                .iter()
+                .any(|method| method.eq_ignore_ascii_case(&request.method))
+        {
+            bail!("the policy does not allow {} requests", request.method);
+        }
+        if let Some(hosts) = &self.allowed_hosts {
+            let url = request_url(request)?;
+            let host = url.host_str().unwrap_or_default();
+            if !hosts
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(host))
+            {
+                bail!("the policy does not allow requests to {host}");
+            }
+        }
+        Ok(())
+    }
+
+    async fn respond(&self, request: HttpRequest) -> anyhow::Result<HttpResponse> {
+        self.check_allowed(&request)?;
+
+        match &self.backend {
+            Backend::Record {
+                path,
+                upstream,
+                recording,
+            } => {
+                let exchange = forward(upstream, &request).await?;
+                let response = exchange.response()?;
+                let mut recording = recording.lock().unwrap();
+                recording.exchanges.push(exchange);
+                let json = serde_json::to_string_pretty(&*recording)?;
+                fs::write(path, json)
+                    .with_context(|| format!("failed to write recording {}", path.display()))?;
+                Ok(response)
+            }
+            Backend::Replay { exchanges, used } => {
+                let (method, url) = (request.method.as_str(), request.url.as_str());
+                let matching = || {
+                    exchanges
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, exchange)| exchange.method == method && exchange.url == url)
+                };
+                // Repeated requests get the recorded responses in order, then the last one again.
+                let mut used = used.lock().unwrap();
+                let (index, exchange) = matching()
+                    .find(|(index, _)| !used[*index])
+                    .or_else(|| matching().next_back())
+                    .ok_or_else(|| anyhow!("no recorded response for {method} {url}"))?;
+                used[index] = true;
+                exchange.response()
+            }
+            Backend::Stub(rules) => {
+                let (method, url) = (request.method.as_str(), request.url.as_str());
+                let rule = rules
+                    .iter()
+                    .find(|rule| rule.matches(method, url))
+                    .ok_or_else(|| anyhow!("no stub rule matches {method} {url}"))?;
+                Ok(response(
+                    rule.status,
+                    rule.headers.clone(),
+                    rule.contents.clone(),
+                ))
+            }
+        }
+    }
+}
+
+impl HttpClient for HttpBackend {
+    fn request(&self, request: HttpRequest) -> BoxFuture<'_, anyhow::Result<HttpResponse>> {
+        Box::pin(async move {
+            let description = format!("{} {}", request.method, request.url);
+            self.respond(request).await.map_err(|err| {
+                log::warn!("Guest HTTP request {description} failed: {err:#}");
+                err
+            })
+        })
+    }
+}
+
+impl Exchange {
+    fn response(&self) -> anyhow::Result<HttpResponse> {
+        Ok(response(
+            self.status,
+            self.headers.clone(),
+            self.body.bytes()?,
+        ))
+    }
+}
+
+fn response(status: u16, headers: Vec<(String, String)>, body: Option<Vec<u8>>) -> HttpResponse {
+    let status_text = StatusCode::from_u16(status)
+        .ok()
+        .and_then(|status| status.canonical_reason())
+        .unwrap_or_default();
+    HttpResponse {
+        pos: 0,
+        body,
+        ok: (200..300).contains(&status),
+        redirected: false,
+        status,
+        status_text: status_text.to_string(),
+        headers,
+    }
+}
+
+/// wasix passes the URL as the guest wrote it.
+fn request_url(request: &HttpRequest) -> anyhow::Result<Url> {
+    Url::parse(&request.url).with_context(|| format!("invalid request URL {}", request.url))
+}
+
+fn load_rules(path: &Path) -> Result<Vec<StubRule>> {
+    let text = fs::read_to_string(path).map_err(Error::file("read stub rules", path))?;
+    let rules: StubRules = if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(&text).map_err(Error::config("stub rules", path))?
+    } else {
+        toml::from_str(&text).map_err(Error::config("stub rules", path))?
+    };
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    rules
+        .rule
+        .into_iter()
+        .map(|mut rule| {
+            rule.contents = match &rule.body_file {
+                Some(file) => {
+                    let file = dir.join(file);
+                    Some(fs::read(&file).map_err(Error::file("read stub body", &file))?)
+                }
+                None => rule.body.clone().map(String::into_bytes),
+            };
+            Ok(rule)
+        })
+        .collect()
+}
+
+/// Header names and values, names in lowercase.
+type Headers = Vec<(String, String)>;
+
+/// Sends `request` to the stand-in server with a bare HTTP/1.1 exchange.
+///
+/// The path and query are kept and the original host goes in the `Host` header, so
+/// the stand-in can tell services apart.
+async fn forward(upstream: &Url, request: &HttpRequest) -> anyhow::Result<Exchange> {
+    let host = upstream
+        .host_str()
+        .context("the upstream URL has no host")?;
+    let port = upstream.port_or_known_default().unwrap_or(80);
+    let mut stream = TcpStream::connect((host, port))
+        .await
+        .with_context(|| format!("failed to connect to the stand-in server at {upstream}"))?;
+
+    let url = request_url(request)?;
+    let mut target = url.path().to_string();
+    if let Some(query) = url.query() {
+        target.push('?');
+        target.push_str(query);
+    }
+    let body = request.body.clone().unwrap_or_default();
+
+    let mut head = format!("{} {target} HTTP/1.1\r\n", request.method);
+    head.push_str(&format!("Host: {}\r\n", url.host_str().unwrap_or(host)));
+    for (name, value) in &request.headers {
+        if !matches!(
+            name.to_ascii_lowercase().as_str(),
+            "host" | "connection" | "content-length" | "transfer-encoding"
+        ) {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+    }
+    head.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    ));
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&body).await?;
+
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).await?;
+    let (status, headers, body) = parse_response(&raw)?;
+
+    Ok(Exchange {
+        method: request.method.clone(),
+        url: request.url.clone(),
+        request_body: Body::new(request.body.clone()),
+        status,
+        headers,
+        body: Body::new(Some(body)),
+    })
+}
+
+fn parse_response(raw: &[u8]) -> anyhow::Result<(u16, Headers, Vec<u8>)> {
+    let (mut status, mut headers, mut body) = parse_head(raw)?;
+    // Interim responses, such as `100 Continue`, come before the final one.
+    while (100..200).contains(&status) && status != 101 {
+        (status, headers, body) = parse_head(body)?;
+    }
+
+    let chunked = headers
+        .iter()
+        .any(|(name, value)| name == "transfer-encoding" && value.eq_ignore_ascii_case("chunked"));
+    let body = match chunked {
+        true => dechunk(body)?,
+        false => body.to_vec(),
+    };
+    // The body is stored decoded, so the framing headers no longer apply.
+    let headers = headers
+        .into_iter()
+        .filter(|(name, _)| !matches!(name.as_str(), "transfer-encoding" | "connection"))
+        .collect();
+
+    Ok((status, headers, body))
+}
+
+/// Reads a status line and headers, returning them with the bytes that follow.
+fn parse_head(raw: &[u8]) -> anyhow::Result<(u16, Headers, &[u8])> {
+    let split = raw
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .context("the stand-in server sent an incomplete response")?;
+    let head = std::str::from_utf8(&raw[..split]).context("invalid response head")?;
+
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .context("invalid response status line")?;
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+
+    Ok((status, headers, &raw[split + 4..]))
+}
+
+fn dechunk(mut data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .context("truncated chunked body")?;
+        let size = std::str::from_utf8(&data[..line_end])?;
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).context("invalid chunk size")?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        if data.len() < size + 2 {
+            bail!("truncated chunked body");
+        }
+        body.extend_from_slice(&data[..size]);
+        data = &data[size + 2..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn parses_a_plain_response() {
+        let raw = b"HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\nmissing";
+        let (status, headers, body) = parse_response(raw).unwrap();
+        assert_eq!(status, 404);
+        assert_eq!(headers, vec![header("content-type", "text/plain")]);
+        assert_eq!(body, b"missing");
+    }
+
+    #[test]
+    fn decodes_chunked_bodies() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n5;ext=1\r\npedia\r\n0\r\n\r\n";
+        let (status, headers, body) = parse_response(raw).unwrap();
+        assert_eq!(status, 200);
+        assert!(headers.is_empty());
+        assert_eq!(body, b"Wikipedia");
+    }
+
+    #[test]
+    fn skips_interim_responses() {
+        let raw =
+            b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 201 Created\r\nLocation: /items/1\r\n\r\n";
+        let (status, headers, body) = parse_response(raw).unwrap();
+        assert_eq!(status, 201);
+        assert_eq!(headers, vec![header("location", "/items/1")]);
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn keeps_binary_bodies() {
+        let mut raw = b"HTTP/1.1 200 OK\r\n\r\n".to_vec();
+        raw.extend_from_slice(&[0, 159, 146, 150, 255]);
+        let (_, _, body) = parse_response(&raw).unwrap();
+        assert_eq!(body, [0, 159, 146, 150, 255]);
+    }
+
+    #[test]
+    fn fills_in_the_wasix_response_fields() {
+        let found = response(200, vec![header("etag", "1")], Some(b"ok".to_vec()));
+        assert!(found.ok);
+        assert_eq!(found.status_text, "OK");
+        assert_eq!(found.headers, vec![header("etag", "1")]);
+
+        let missing = response(404, Vec::new(), None);
+        assert!(!missing.ok);
+        assert_eq!(missing.status_text, "Not Found");
+        assert_eq!(response(799, Vec::new(), None).status_text, "");
+    }
+
+    #[test]
+    fn rejects_incomplete_responses() {
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 3").is_err());
+        assert!(parse_response(b"garbage\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn dechunk_rejects_truncated_bodies() {
+        assert_eq!(dechunk(b"3\r\nabc\r\n0\r\n\r\n").unwrap(), b"abc");
+        assert!(dechunk(b"a\r\nabc\r\n").is_err());
+        assert!(dechunk(b"3\r\nabc\r\n").is_err());
+        assert!(dechunk(b"zz\r\nabc\r\n").is_err());
+    }
+}
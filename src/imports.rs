@@ -0,0 +1,426 @@
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+use wasmer::{
+    AsStoreMut, Extern, ExternType, Function, FunctionType, Imports, Module, RuntimeError, Type,
+    Value,
+};
+
+use wasmer_types::ModuleInfo;
+
+use crate::error::{Error, Result};
+
+/// Namespaces whose imports are provided by wasmer-wasix rather than by us.
+const WASI_NAMESPACES: &[&str] = &[
+    "wasi_unstable",
+    "wasi_snapshot_preview1",
+    "wasi",
+    "wasix_32v1",
+    "wasix_64v1",
+];
+
+pub type HostCallback = Arc<dyn Fn(&[Value]) -> Result<Vec<Value>, RuntimeError> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct HostFunction {
+    pub ty: FunctionType,
+    pub callback: HostCallback,
+}
+
+/// Host functions the guest can import, keyed by `(namespace, name)`.
+#[derive(Clone, Default)]
+pub struct HostImports {
+    functions: BTreeMap<(String, String), HostFunction>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportProblem {
+    Missing {
+        namespace: String,
+        name: String,
+        ty: ExternType,
+    },
+    SignatureMismatch {
+        namespace: String,
+        name: String,
+        expected: ExternType,
+        provided: FunctionType,
+    },
+}
+
+impl fmt::Display for ImportProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportProblem::Missing {
+                namespace,
+                name,
+                ty,
+            } => {
+                write!(
+                    f,
+                    "{namespace}.{name} is not provided by the host (guest expects {ty:?})"
+                )
+            }
+            ImportProblem::SignatureMismatch {
+                namespace,
+                name,
+                expected,
+                provided,
+            } => write!(
+                f,
+                "{namespace}.{name} has signature {provided}, but the guest expects {expected:?}"
+            ),
+        }
+    }
+}
+
+impl HostImports {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a host function, replacing any previous one with the same name.
+    pub fn register<F>(
+        &mut self,
+        namespace: impl Into<String>,
+        name: impl Into<String>,
+        ty: FunctionType,
+        callback: F,
+    ) -> &mut Self
+    where
+        F: Fn(&[Value]) -> Result<Vec<Value>, RuntimeError> + Send + Sync + 'static,
+    {
+        self.functions.insert(
+            (namespace.into(), name.into()),
+            HostFunction {
+                ty,
+                callback: Arc::new(callback),
+            },
+        );
+        self
+    }
+
+    pub fn get(&self, namespace: &str, name: &str) -> Option<&HostFunction> {
+        self.functions
+            .get(&(namespace.to_string(), name.to_string()))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str, &HostFunction)> {
+        self.functions
+            .iter()
+            .map(|((namespace, name), function)| (namespace.as_str(), name.as_str(), function))
+    }
+
+    /// Compares the module's non-WASI imports against the registry, skipping those
+    /// already in `provided`, e.g. by WAI bindings.
+    pub fn check(&self, module: &ModuleInfo, provided: &Imports) -> Vec<ImportProblem> {
+        let mut problems = Vec::new();
+        for import in module.imports() {
+            if is_wasi_namespace(import.module()) || provided.exists(import.module(), import.name())
+            {
+                continue;
+            }
+            let expected = import.ty().clone();
+            match (self.get(import.module(), import.name()), &expected) {
+                (Some(function), ExternType::Function(ty)) if function.ty == *ty => {}
+                (Some(function), _) => problems.push(ImportProblem::SignatureMismatch {
+                    namespace: import.module().to_string(),
+                    name: import.name().to_string(),
+                    expected,
+                    provided: function.ty.clone(),
+                }),
+                (None, _) => problems.push(ImportProblem::Missing {
+                    namespace: import.module().to_string(),
+                    name: import.name().to_string(),
+                    ty: expected,
+                }),
+            }
+        }
+        problems
+    }
+
+    /// Checks the registry against the module and creates the functions it imports.
+    pub fn resolve(
+        &self,
+        store: &mut impl AsStoreMut,
+        module: &Module,
+        provided: &Imports,
+    ) -> Result<Vec<((String, String), Extern)>> {
+        let problems = self.check(module.info(), provided);
+        if !problems.is_empty() {
+            return Err(Error::UnsatisfiedImports(problems));
+        }
+        Ok(self.create(store, module, provided))
+    }
+
+    /// Like [`HostImports::resolve`], but fills every missing function with a stub
+    /// that only traps once the guest calls it.
+    ///
+    /// WASI functions are stubbed too when `provided` lacks them, so it should hold
+    /// the WASI imports, e.g. for `wasi.thread-spawn`, which wasmer-wasix leaves out.
+    /// Signature mismatches and missing memories, tables or globals are still errors.
+    pub fn resolve_with_stubs(
+        &self,
+        store: &mut impl AsStoreMut,
+        module: &Module,
+        provided: &Imports,
+    ) -> Result<Vec<((String, String), Extern)>> {
+        let mut stubs = Vec::new();
+        let mut problems = Vec::new();
+        for problem in self.check(module.info(), provided) {
+            match problem {
+                ImportProblem::Missing {
+                    namespace,
+                    name,
+                    ty: ExternType::Function(ty),
+                } => {
+                    log::warn!(
+                        "Stubbing missing import {namespace}.{name}, the guest traps if it calls it"
+                    );
+                    let stub = trapping_stub(store, &namespace, &name, ty);
+                    stubs.push(((namespace, name), Extern::Function(stub)));
+                }
+                problem => problems.push(problem),
+            }
+        }
+        if !problems.is_empty() {
+            return Err(Error::UnsatisfiedImports(problems));
+        }
+        for import in module.imports() {
+            let (namespace, name) = (import.module(), import.name());
+            if let ExternType::Function(ty) = import.ty() {
+                if is_wasi_namespace(namespace) && !provided.exists(namespace, name) {
+                    log::warn!(
+                        "Stubbing missing WASI import {namespace}.{name}, the guest traps if it \
+                         calls it"
+                    );
+                    let stub = trapping_stub(store, namespace, name, ty.clone());
+                    stubs.push((
+                        (namespace.to_string(), name.to_string()),
+                        Extern::Function(stub),
+                    ));
+                }
+            }
+        }
+
+        let mut externs = self.create(store, module, provided);
+        externs.extend(stubs);
+        Ok(externs)
+    }
+
+    fn create(
+        &self,
+        store: &mut impl AsStoreMut,
+        module: &Module,
+        provided: &Imports,
+    ) -> Vec<((String, String), Extern)> {
+        let mut externs = Vec::new();
+        for import in module.imports() {
+            if provided.exists(import.module(), import.name()) {
+                continue;
+            }
+            let Some(function) = self.get(import.module(), import.name()) else {
+                continue;
+            };
+            let callback = function.callback.clone();
+            let function = Function::new(store, function.ty.clone(), move |args| callback(args));
+            externs.push((
+                (import.module().to_string(), import.name().to_string()),
+                Extern::Function(function),
+            ));
+        }
+        externs
+    }
+}
+
+/// A function of the imported type whose every call fails with an explanation of
+/// what the guest expected the host to provide.
+fn trapping_stub(
+    store: &mut impl AsStoreMut,
+    namespace: &str,
+    name: &str,
+    ty: FunctionType,
+) -> Function {
+    let message = format!(
+        "the guest called {namespace}.{name}, which the host doesn't implement. It is \
+         likely declared in C# as:\n\n{}",
+        dllimport_declaration(namespace, name, &ty)
+    );
+    Function::new(store, ty, move |_| Err(RuntimeError::new(message.clone())))
+}
+
+/// The `DllImport` a C# guest most likely declares for an import, with the
+/// closest C# types for the wasm ones. An `int` may also be a pointer or `bool`.
+///
+/// NativeAOT-LLVM only turns a `DllImport` into a wasm import with
+/// `[WasmImportLinkage]`, otherwise it expects to link the function statically.
+pub fn dllimport_declaration(namespace: &str, name: &str, ty: &FunctionType) -> String {
+    // WAI guests import `name: func(...)`, keep only the name for the method.
+    let method: String = name
+        .split(':')
+        .next()
+        .unwrap_or(name)
+        .trim()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    let result = match ty.results() {
+        [] => "void".to_string(),
+        [result] => csharp_type(*result).to_string(),
+        results => format!(
+            "({})",
+            results
+                .iter()
+                .map(|ty| csharp_type(*ty))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let params = ty
+        .params()
+        .iter()
+        .enumerate()
+        .map(|(index, ty)| format!("{} arg{index}", csharp_type(*ty)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "    [DllImport(\"{namespace}\", EntryPoint = \"{name}\")]\n    \
+         [WasmImportLinkage]\n    \
+         static extern {result} {method}({params});"
+    )
+}
+
+fn csharp_type(ty: Type) -> &'static str {
+    match ty {
+        Type::I32 => "int",
+        Type::I64 => "long",
+        Type::F32 => "float",
+        Type::F64 => "double",
+        Type::V128 => "Vector128<byte>",
+        Type::ExternRef | Type::FuncRef => "nint",
+    }
+}
+
+pub fn is_wasi_namespace(namespace: &str) -> bool {
+    WASI_NAMESPACES.contains(&namespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmer::{Instance, Store};
+
+    use super::*;
+
+    const GUEST: &str = r#"(module
+        (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+        (import "wasi" "thread-spawn" (func (param i32) (result i32)))
+        (import "rust" "wasmImportFloat32Param" (func (param f32)))
+        (import "rust" "wasmImportFloat64Result" (func (result f64)))
+        (import "rust" "wasmImportInt" (func (param i32)))
+        (func (export "spawn") (result i32) i32.const 0 call 1))"#;
+
+    fn host() -> HostImports {
+        let mut host = HostImports::new();
+        host.register(
+            "rust",
+            "wasmImportFloat32Param",
+            FunctionType::new([Type::F32], []),
+            |_| Ok(vec![]),
+        )
+        .register(
+            "rust",
+            "wasmImportFloat64Result",
+            FunctionType::new([], [Type::F32]),
+            |_| Ok(vec![Value::F32(1.0)]),
+        );
+        host
+    }
+
+    #[test]
+    fn reports_missing_and_mismatched_imports() {
+        let store = Store::default();
+        let module = Module::new(&store, GUEST).unwrap();
+        let problems = host().check(module.info(), &Imports::new());
+
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(matches!(
+            &problems[0],
+            ImportProblem::SignatureMismatch { name, .. } if name == "wasmImportFloat64Result"
+        ));
+        assert!(matches!(
+            &problems[1],
+            ImportProblem::Missing { name, .. } if name == "wasmImportInt"
+        ));
+    }
+
+    #[test]
+    fn skips_imports_that_are_already_provided() {
+        let mut store = Store::default();
+        let module = Module::new(&store, GUEST).unwrap();
+        let mut provided = Imports::new();
+        for name in ["wasmImportFloat64Result", "wasmImportInt"] {
+            let ty = FunctionType::new([], []);
+            provided.define("rust", name, Function::new(&mut store, ty, |_| Ok(vec![])));
+        }
+
+        assert_eq!(host().check(module.info(), &provided), vec![]);
+        let externs = host().resolve(&mut store, &module, &provided).unwrap();
+        let names: Vec<&str> = externs.iter().map(|((_, name), _)| name.as_str()).collect();
+        assert_eq!(names, ["wasmImportFloat32Param"]);
+    }
+
+    #[test]
+    fn stubs_missing_functions_including_wasi_ones() {
+        let mut store = Store::default();
+        let module = Module::new(&store, GUEST).unwrap();
+        let mut host = host();
+        host.register(
+            "rust",
+            "wasmImportFloat64Result",
+            FunctionType::new([], [Type::F64]),
+            |_| Ok(vec![Value::F64(1.0)]),
+        );
+        let mut provided = Imports::new();
+        let proc_exit = Function::new(&mut store, FunctionType::new([Type::I32], []), |_| {
+            Ok(vec![])
+        });
+        provided.define("wasi_snapshot_preview1", "proc_exit", proc_exit);
+
+        let externs = host
+            .resolve_with_stubs(&mut store, &module, &provided)
+            .unwrap();
+        let mut imports = provided.clone();
+        imports.extend(externs);
+        let instance = Instance::new(&mut store, &module, &imports).unwrap();
+
+        let spawn = instance.exports.get_function("spawn").unwrap();
+        let err = spawn.call(&mut store, &[]).unwrap_err();
+        assert!(
+            err.message().contains("wasi.thread-spawn"),
+            "{}",
+            err.message()
+        );
+    }
+
+    #[test]
+    fn stubs_are_no_excuse_for_mismatched_signatures() {
+        let mut store = Store::default();
+        let module = Module::new(&store, GUEST).unwrap();
+        match host().resolve_with_stubs(&mut store, &module, &Imports::new()) {
+            Err(Error::UnsatisfiedImports(problems)) => assert_eq!(problems.len(), 1),
+            Err(err) => panic!("unexpected error: {err}"),
+            Ok(_) => panic!("a mismatched signature was stubbed"),
+        }
+    }
+
+    #[test]
+    fn declares_imports_with_wasm_import_linkage() {
+        let ty = FunctionType::new([Type::I32, Type::F64], [Type::I64]);
+        assert_eq!(
+            dllimport_declaration("rust", "wasm-import: func(x: s32, y: float64) -> s64", &ty),
+            "    [DllImport(\"rust\", EntryPoint = \"wasm-import: func(x: s32, y: float64) -> \
+             s64\")]\n    \
+             [WasmImportLinkage]\n    \
+             static extern long wasm_import(int arg0, double arg1);"
+        );
+    }
+}
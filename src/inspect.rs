@@ -0,0 +1,357 @@
+use std::{collections::BTreeMap, fmt};
+
+use serde::Serialize;
+use wasmer::{ExternType, Features, Mutability};
+use wasmer_types::ModuleInfo;
+use wasmparser::{BinaryReader, Parser, Payload, TableType, TypeRef, ValType};
+
+use crate::{
+    error::Result,
+    features::enabled_features,
+    profile::DotnetRuntime,
+    values::{format_signature, type_name},
+};
+
+/// What a module imports, exports and declares, and what the host can't give it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModuleReport {
+    pub size: usize,
+    pub toolchain: Toolchain,
+    /// Proposals the engine enables for the module: those detected in it, or given
+    /// explicitly, with the forced ones on or off.
+    pub features: Vec<&'static str>,
+    /// Imports grouped by namespace.
+    pub imports: BTreeMap<String, Vec<Item>>,
+    pub exports: Vec<Item>,
+    pub memories: Vec<MemoryInfo>,
+    pub tables: Vec<TableInfo>,
+    pub custom_sections: Vec<CustomSection>,
+    /// Entries of the `target_features` section, e.g. `+bulk-memory`.
+    pub target_features: Vec<String>,
+    /// Imports the configured host can't satisfy, empty when the module would link.
+    pub unsatisfied_imports: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Toolchain {
+    pub runtime: Option<DotnetRuntime>,
+    /// Languages and tools from the `producers` section, e.g. `clang 16.0.0`.
+    pub producers: Vec<String>,
+    pub debug_info: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Item {
+    pub name: String,
+    pub kind: &'static str,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryInfo {
+    /// `namespace.name` of an imported memory.
+    pub import: Option<String>,
+    pub initial: u64,
+    pub maximum: Option<u64>,
+    pub shared: bool,
+    pub memory64: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TableInfo {
+    /// `namespace.name` of an imported table.
+    pub import: Option<String>,
+    pub element: &'static str,
+    pub initial: u32,
+    pub maximum: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CustomSection {
+    pub name: String,
+    pub size: usize,
+}
+
+impl ModuleReport {
+    /// Describes a module from what wasmer parsed of it and its bytes, and the
+    /// proposals it is compiled with.
+    pub fn new(module: &ModuleInfo, bytes: &[u8], features: &Features) -> Result<Self> {
+        let mut imports: BTreeMap<String, Vec<Item>> = BTreeMap::new();
+        for import in module.imports() {
+            imports
+                .entry(import.module().to_string())
+                .or_default()
+                .push(Item::new(import.name(), import.ty()));
+        }
+        let exports = module
+            .exports()
+            .map(|export| Item::new(export.name(), export.ty()))
+            .collect();
+
+        let mut report = ModuleReport {
+            size: bytes.len(),
+            toolchain: Toolchain {
+                runtime: DotnetRuntime::detect(bytes),
+                ..Toolchain::default()
+            },
+            features: enabled_features(features),
+            imports,
+            exports,
+            memories: Vec::new(),
+            tables: Vec::new(),
+            custom_sections: Vec::new(),
+            target_features: Vec::new(),
+            unsatisfied_imports: Vec::new(),
+        };
+        report.read_sections(bytes)?;
+        Ok(report)
+    }
+
+    fn read_sections(&mut self, bytes: &[u8]) -> Result<()> {
+        for payload in Parser::new(0).parse_all(bytes) {
+            match payload? {
+                Payload::ImportSection(reader) => {
+                    for import in reader {
+                        let import = import?;
+                        let name = Some(format!("{}.{}", import.module, import.name));
+                        match import.ty {
+                            TypeRef::Memory(memory) => self.memories.push(MemoryInfo {
+                                import: name,
+                                initial: memory.initial,
+                                maximum: memory.maximum,
+                                shared: memory.shared,
+                                memory64: memory.memory64,
+                            }),
+                            TypeRef::Table(table) => self.tables.push(TableInfo::new(name, table)),
+                            _ => {}
+                        }
+                    }
+                }
+                Payload::MemorySection(reader) => {
+                    for memory in reader {
+                        let memory = memory?;
+                        self.memories.push(MemoryInfo {
+                            import: None,
+                            initial: memory.initial,
+                            maximum: memory.maximum,
+                            shared: memory.shared,
+                            memory64: memory.memory64,
+                        });
+                    }
+                }
+                Payload::TableSection(reader) => {
+                    for table in reader {
+                        self.tables.push(TableInfo::new(None, table?));
+                    }
+                }
+                Payload::CustomSection(section) => {
+                    match section.name() {
+                        "target_features" => {
+                            self.target_features = read_target_features(section.data())?
+                        }
+                        "producers" => self.toolchain.producers = read_producers(section.data())?,
+                        name if name.starts_with(".debug_") => self.toolchain.debug_info = true,
+                        _ => {}
+                    }
+                    self.custom_sections.push(CustomSection {
+                        name: section.name().to_string(),
+                        size: section.data().len(),
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Item {
+    fn new(name: &str, ty: &ExternType) -> Self {
+        let (kind, signature) = match ty {
+            ExternType::Function(ty) => ("func", format_signature(name, ty)),
+            ExternType::Global(global) => {
+                let mutability = match global.mutability {
+                    Mutability::Var => "mut ",
+                    Mutability::Const => "",
+                };
+                let ty = type_name(global.ty);
+                ("global", format!("{name}: {mutability}{ty}"))
+            }
+            ExternType::Memory(memory) => {
+                let maximum = memory.maximum.map(|max| max.0.to_string());
+                let shared = if memory.shared { ", shared" } else { "" };
+                let limits = format_limits(memory.minimum.0, maximum);
+                ("memory", format!("{name}: {limits} pages{shared}"))
+            }
+            ExternType::Table(table) => {
+                let maximum = table.maximum.map(|max| max.to_string());
+                let limits = format_limits(table.minimum, maximum);
+                let ty = type_name(table.ty);
+                ("table", format!("{name}: {limits} {ty}"))
+            }
+        };
+        Item {
+            name: name.to_string(),
+            kind,
+            signature,
+        }
+    }
+}
+
+impl TableInfo {
+    fn new(import: Option<String>, table: TableType) -> Self {
+        TableInfo {
+            import,
+            element: match table.element_type {
+                ValType::I32 => "i32",
+                ValType::I64 => "i64",
+                ValType::F32 => "f32",
+                ValType::F64 => "f64",
+                ValType::V128 => "v128",
+                ValType::FuncRef => "funcref",
+                ValType::ExternRef => "externref",
+            },
+            initial: table.initial,
+            maximum: table.maximum,
+        }
+    }
+}
+
+fn format_limits(minimum: impl fmt::Display, maximum: Option<String>) -> String {
+    match maximum {
+        Some(maximum) => format!("{minimum}..{maximum}"),
+        None => format!("{minimum}.."),
+    }
+}
+
+/// Reads `+feature` entries: a count, then a prefix byte and a name for each.
+pub(crate) fn read_target_features(data: &[u8]) -> Result<Vec<String>> {
+    let mut reader = BinaryReader::new(data);
+    let count = reader.read_var_u32()?;
+    let mut features = Vec::new();
+    for _ in 0..count {
+        let prefix = reader.read_u8()? as char;
+        let name = reader.read_string()?;
+        features.push(format!("{prefix}{name}"));
+    }
+    Ok(features)
+}
+
+/// Reads the fields of the tool conventions' `producers` section as `name version`.
+fn read_producers(data: &[u8]) -> Result<Vec<String>> {
+    let mut reader = BinaryReader::new(data);
+    let mut producers = Vec::new();
+    for _ in 0..reader.read_var_u32()? {
+        let _field = reader.read_string()?;
+        for _ in 0..reader.read_var_u32()? {
+            let name = reader.read_string()?;
+            let version = reader.read_string()?;
+            producers.push(format!("{name} {version}").trim_end().to_string());
+        }
+    }
+    Ok(producers)
+}
+
+impl fmt::Display for ModuleReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let toolchain = &self.toolchain;
+        writeln!(f, "Size: {} bytes", self.size)?;
+        match toolchain.runtime {
+            Some(runtime) => writeln!(f, "Runtime: {runtime}")?,
+            None => writeln!(f, "Runtime: not recognized")?,
+        }
+        if !toolchain.producers.is_empty() {
+            writeln!(f, "Producers: {}", toolchain.producers.join(", "))?;
+        }
+        writeln!(
+            f,
+            "Debug info: {}",
+            if toolchain.debug_info {
+                "DWARF"
+            } else {
+                "none"
+            }
+        )?;
+
+        writeln!(
+            f,
+            "Features: {}",
+            if self.features.is_empty() {
+                "none beyond the MVP".to_string()
+            } else {
+                self.features.join(", ")
+            }
+        )?;
+
+        let count: usize = self.imports.values().map(Vec::len).sum();
+        writeln!(f, "\nImports ({count}):")?;
+        for (namespace, items) in &self.imports {
+            writeln!(f, "  {namespace} ({}):", items.len())?;
+            for item in items {
+                writeln!(f, "    {}", item.signature)?;
+            }
+        }
+
+        writeln!(f, "\nExports ({}):", self.exports.len())?;
+        for item in &self.exports {
+            writeln!(f, "  {}", item.signature)?;
+        }
+
+        writeln!(f, "\nMemories ({}):", self.memories.len())?;
+        for (index, memory) in self.memories.iter().enumerate() {
+            let maximum = memory.maximum.map(|max| max.to_string());
+            write!(
+                f,
+                "  {index}: {} pages",
+                format_limits(memory.initial, maximum)
+            )?;
+            if memory.shared {
+                write!(f, ", shared")?;
+            }
+            if memory.memory64 {
+                write!(f, ", 64-bit")?;
+            }
+            if let Some(import) = &memory.import {
+                write!(f, ", imported from {import}")?;
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f, "\nTables ({}):", self.tables.len())?;
+        for (index, table) in self.tables.iter().enumerate() {
+            let maximum = table.maximum.map(|max| max.to_string());
+            write!(
+                f,
+                "  {index}: {} {}",
+                format_limits(table.initial, maximum),
+                table.element
+            )?;
+            if let Some(import) = &table.import {
+                write!(f, ", imported from {import}")?;
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f, "\nCustom sections ({}):", self.custom_sections.len())?;
+        for section in &self.custom_sections {
+            writeln!(f, "  {} ({} bytes)", section.name, section.size)?;
+        }
+        if !self.target_features.is_empty() {
+            writeln!(f, "\nTarget features: {}", self.target_features.join(" "))?;
+        }
+
+        if self.unsatisfied_imports.is_empty() {
+            writeln!(f, "\nThe host satisfies every import.")
+        } else {
+            writeln!(
+                f,
+                "\nUnsatisfied imports ({}):",
+                self.unsatisfied_imports.len()
+            )?;
+            for problem in &self.unsatisfied_imports {
+                writeln!(f, "  {problem}")?;
+            }
+            Ok(())
+        }
+    }
+}
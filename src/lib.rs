@@ -0,0 +1,40 @@
+//! Host for C# guests compiled to WASI, built on wasmer and wasmer-wasix.
+//!
+//! [`CsGuestRuntime`] configures and instantiates a guest, the returned
+//! [`GuestInstance`] runs its `_start` or calls its exports.
+//!
+//! Diagnostics, such as module cache hits or stubbed imports, go through the `log`
+//! crate rather than straight to stderr.
+
+pub mod artifact;
+pub mod cache;
+pub mod compiler;
+pub mod dwarf;
+pub mod error;
+pub mod features;
+pub mod http;
+pub mod imports;
+pub mod inspect;
+pub mod limits;
+pub mod mounts;
+pub mod net;
+pub mod outcome;
+pub mod policy;
+pub mod profile;
+pub mod reactor;
+mod runtime;
+pub mod stdin;
+pub mod stdio;
+pub mod symbols;
+pub mod tunables;
+pub mod values;
+pub mod wai;
+
+pub use crate::{
+    error::{Error, Result},
+    outcome::Outcome,
+    reactor::Reactor,
+    runtime::{
+        CacheConfig, CsGuestRuntime, GuestInstance, ModuleSource, RunReport, DEFAULT_PROGRAM_NAME,
+    },
+};
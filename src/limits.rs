@@ -0,0 +1,331 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use wasmer::{
+    wasmparser::{BlockType, Operator, ValType},
+    AsStoreMut, ExportIndex, Extern, Function, FunctionEnv, FunctionEnvMut, FunctionMiddleware,
+    Global, GlobalInit, GlobalType, Imports, Instance, LocalFunctionIndex, MiddlewareError,
+    MiddlewareReaderState, Module, ModuleMiddleware, Mutability, RuntimeError, Type, Value,
+};
+use wasmer_middlewares::{
+    metering::{get_remaining_points, set_remaining_points, MeteringPoints},
+    Metering,
+};
+use wasmer_types::{FunctionIndex, GlobalIndex, ModuleInfo};
+
+use crate::error::{Error, Result};
+
+/// Middleware that charges one unit of fuel per executed operator.
+///
+/// The limit compiled into the module is never used: [`set_fuel`] sets the real
+/// one on each instance, so cached artifacts work for any limit.
+pub fn metering() -> Arc<dyn ModuleMiddleware> {
+    Arc::new(Metering::new(u64::MAX, |_: &Operator| 1))
+}
+
+pub fn set_fuel(store: &mut impl AsStoreMut, instance: &Instance, fuel: u64) {
+    set_remaining_points(store, instance, fuel);
+}
+
+/// Remaining fuel, or `None` once the guest has run out.
+pub fn remaining_fuel(store: &mut impl AsStoreMut, instance: &Instance) -> Option<u64> {
+    match get_remaining_points(store, instance) {
+        MeteringPoints::Remaining(points) => Some(points),
+        MeteringPoints::Exhausted => None,
+    }
+}
+
+/// Operators a guest runs between looking at the clock.
+const CHECK_IN_INTERVAL: u64 = 10_000;
+
+/// Set while the guest calls its first import to check in rather than for real.
+const CHECKING_IN_EXPORT: &str = "cs_deadline_checking_in";
+
+/// Middleware that makes the guest hand control back to the host every
+/// [`CHECK_IN_INTERVAL`] operators, so [`Deadline`] can stop it.
+///
+/// WebAssembly can only reach the host through its imports, so the check-in calls
+/// the module's first imported function with a flag set, and [`Deadline`] answers
+/// such calls itself. Add it after [`metering`], so that check-ins cost no fuel.
+pub fn deadline() -> Arc<dyn ModuleMiddleware> {
+    Arc::new(DeadlineMiddleware::default())
+}
+
+#[derive(Debug, Clone)]
+struct CheckInTarget {
+    countdown: GlobalIndex,
+    checking_in: GlobalIndex,
+    function: FunctionIndex,
+    params: Vec<Type>,
+    results: usize,
+}
+
+#[derive(Debug, Default)]
+struct DeadlineMiddleware {
+    /// `None` until the module is known, and for modules that import no functions.
+    target: Mutex<Option<CheckInTarget>>,
+}
+
+impl ModuleMiddleware for DeadlineMiddleware {
+    fn generate_function_middleware(&self, _: LocalFunctionIndex) -> Box<dyn FunctionMiddleware> {
+        Box::new(FunctionDeadline {
+            target: self.target.lock().unwrap().clone(),
+            accumulated: 0,
+        })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) {
+        if module_info.num_imported_functions == 0 {
+            return;
+        }
+        let function = FunctionIndex::from_u32(0);
+        let ty = &module_info.signatures[module_info.functions[function]];
+        let (params, results) = (ty.params().to_vec(), ty.results().len());
+
+        let countdown = module_info
+            .globals
+            .push(GlobalType::new(Type::I64, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I64Const(CHECK_IN_INTERVAL as i64));
+
+        let checking_in = module_info
+            .globals
+            .push(GlobalType::new(Type::I32, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I32Const(0));
+        module_info.exports.insert(
+            CHECKING_IN_EXPORT.to_string(),
+            ExportIndex::Global(checking_in),
+        );
+
+        *self.target.lock().unwrap() = Some(CheckInTarget {
+            countdown,
+            checking_in,
+            function,
+            params,
+            results,
+        });
+    }
+}
+
+#[derive(Debug)]
+struct FunctionDeadline {
+    target: Option<CheckInTarget>,
+    accumulated: u64,
+}
+
+impl FunctionMiddleware for FunctionDeadline {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        let Some(target) = &self.target else {
+            state.push_operator(operator);
+            return Ok(());
+        };
+        self.accumulated += 1;
+
+        // The same block boundaries the metering middleware counts at.
+        if matches!(
+            operator,
+            Operator::Loop { .. }
+                | Operator::End
+                | Operator::Else
+                | Operator::Br { .. }
+                | Operator::BrTable { .. }
+                | Operator::BrIf { .. }
+                | Operator::Call { .. }
+                | Operator::CallIndirect { .. }
+                | Operator::Return
+        ) {
+            let cost = self.accumulated as i64;
+            let countdown = target.countdown.as_u32();
+            let checking_in = target.checking_in.as_u32();
+            self.accumulated = 0;
+
+            // if countdown < cost { check in; countdown = interval }
+            state.extend(&[
+                Operator::GlobalGet {
+                    global_index: countdown,
+                },
+                Operator::I64Const { value: cost },
+                Operator::I64LtU,
+                Operator::If {
+                    blockty: BlockType::Empty,
+                },
+                Operator::I32Const { value: 1 },
+                Operator::GlobalSet {
+                    global_index: checking_in,
+                },
+            ]);
+            for param in &target.params {
+                state.extend(&zero(*param));
+            }
+            state.push_operator(Operator::Call {
+                function_index: target.function.as_u32(),
+            });
+            for _ in 0..target.results {
+                state.push_operator(Operator::Drop);
+            }
+            state.extend(&[
+                Operator::I64Const {
+                    value: (CHECK_IN_INTERVAL as i64).max(cost),
+                },
+                Operator::GlobalSet {
+                    global_index: countdown,
+                },
+                Operator::End,
+                // countdown -= cost
+                Operator::GlobalGet {
+                    global_index: countdown,
+                },
+                Operator::I64Const { value: cost },
+                Operator::I64Sub,
+                Operator::GlobalSet {
+                    global_index: countdown,
+                },
+            ]);
+        }
+
+        state.push_operator(operator);
+        Ok(())
+    }
+}
+
+/// Operators that push a zero of type `ty`, for the arguments of a check-in call.
+fn zero(ty: Type) -> Vec<Operator<'static>> {
+    match ty {
+        Type::I32 => vec![Operator::I32Const { value: 0 }],
+        Type::I64 => vec![Operator::I64Const { value: 0 }],
+        Type::F32 => vec![Operator::I32Const { value: 0 }, Operator::F32ReinterpretI32],
+        Type::F64 => vec![Operator::I64Const { value: 0 }, Operator::F64ReinterpretI64],
+        Type::V128 => vec![Operator::I64Const { value: 0 }, Operator::I64x2Splat],
+        Type::ExternRef => vec![Operator::RefNull {
+            ty: ValType::ExternRef,
+        }],
+        Type::FuncRef => vec![Operator::RefNull {
+            ty: ValType::FuncRef,
+        }],
+    }
+}
+
+fn zero_value(ty: &Type) -> Value {
+    match ty {
+        Type::I32 => Value::I32(0),
+        Type::I64 => Value::I64(0),
+        Type::F32 => Value::F32(0.0),
+        Type::F64 => Value::F64(0.0),
+        Type::V128 => Value::V128(0),
+        Type::ExternRef => Value::ExternRef(None),
+        Type::FuncRef => Value::FuncRef(None),
+    }
+}
+
+/// The trap raised in a guest that checks in after its deadline.
+#[derive(Debug, thiserror::Error)]
+#[error("the guest ran past its deadline")]
+pub struct DeadlineExceeded;
+
+struct CheckIn {
+    /// The import the guest checks in through, called for real otherwise.
+    import: Function,
+    checking_in: Option<Global>,
+    deadline: Option<Instant>,
+}
+
+/// Stops a guest compiled with [`deadline`] at its first check-in past the deadline,
+/// with a [`DeadlineExceeded`] trap.
+///
+/// The guest is stopped on its own thread, so it can be called again afterwards. A
+/// guest blocked in a host call, e.g. reading stdin, only stops once the call
+/// returns and it checks in again.
+pub struct Deadline {
+    env: FunctionEnv<CheckIn>,
+}
+
+impl Deadline {
+    /// Puts the host's side of the check-ins in front of the module's first
+    /// imported function, which must already be in `imports`.
+    pub fn wrap(
+        store: &mut impl AsStoreMut,
+        module: &Module,
+        imports: &mut Imports,
+    ) -> Result<Self> {
+        let target = module.imports().functions().next().ok_or_else(|| {
+            Error::Limit(
+                "the module imports no functions, so it can't be stopped at a deadline".to_string(),
+            )
+        })?;
+        let Some(Extern::Function(import)) = imports.get_export(target.module(), target.name())
+        else {
+            return Err(Error::Limit(format!(
+                "the deadline check-in needs the import {}.{}",
+                target.module(),
+                target.name()
+            )));
+        };
+
+        let ty = target.ty().clone();
+        let env = FunctionEnv::new(
+            store,
+            CheckIn {
+                import,
+                checking_in: None,
+                deadline: None,
+            },
+        );
+        let check_in = Function::new_with_env(store, &env, ty.clone(), move |env, args| {
+            check_in(env, args, ty.results())
+        });
+        imports.define(target.module(), target.name(), check_in);
+        Ok(Deadline { env })
+    }
+
+    /// Connects the check-ins of the instance made with the wrapped imports.
+    pub fn attach(&self, store: &mut impl AsStoreMut, instance: &Instance) -> Result<()> {
+        let checking_in = instance
+            .exports
+            .get_global(CHECKING_IN_EXPORT)
+            .map_err(|_| {
+                Error::Limit("the module was compiled without deadline checks".to_string())
+            })?
+            .clone();
+        self.env.as_mut(store).checking_in = Some(checking_in);
+        Ok(())
+    }
+
+    /// Starts the clock for the next call into the guest.
+    pub fn arm(&self, store: &mut impl AsStoreMut, timeout: Duration) {
+        self.env.as_mut(store).deadline = Instant::now().checked_add(timeout);
+    }
+
+    pub fn disarm(&self, store: &mut impl AsStoreMut) {
+        self.env.as_mut(store).deadline = None;
+    }
+}
+
+fn check_in(
+    mut env: FunctionEnvMut<CheckIn>,
+    args: &[Value],
+    results: &[Type],
+) -> Result<Vec<Value>, RuntimeError> {
+    let (check_in, mut store) = env.data_and_store_mut();
+    let (import, deadline) = (check_in.import.clone(), check_in.deadline);
+    let Some(checking_in) = check_in.checking_in.clone() else {
+        return Ok(import.call(&mut store, args)?.into_vec());
+    };
+    if checking_in.get(&mut store) == Value::I32(0) {
+        return Ok(import.call(&mut store, args)?.into_vec());
+    }
+
+    checking_in.set(&mut store, Value::I32(0))?;
+    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        return Err(RuntimeError::user(Box::new(DeadlineExceeded)));
+    }
+    Ok(results.iter().map(zero_value).collect())
+}
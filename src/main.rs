@@ -1,123 +1,305 @@
-use std::{io::Read, sync::Arc};
+mod cli;
 
-use eyre::Result;
-use tokio::runtime::Handle;
-use virtual_fs::Pipe;
-use wasmer::{
-    imports, EngineBuilder, Extern, Features, Function, FunctionType, Instance, Module, Store,
-    Type, Value,
+use std::{
+    io::{self, BufRead},
+    path::Path,
+    process::ExitCode,
+};
+
+use clap::Parser;
+use cs_runtime_example::{
+    cache::{default_cache_dir, ModuleCache},
+    net::LoopbackNetworking,
+    policy::{insecure_capabilities, Policy},
+    stdin::StdinSource,
+    symbols::Symbolizer,
+    values::{format_signature, format_value},
+    CacheConfig, CsGuestRuntime, Error, ModuleSource, Outcome,
 };
-use wasmer_compiler_cranelift::Cranelift;
-use wasmer_wasix::{
-    capabilities::{Capabilities, CapabilityThreadingV1},
-    http::HttpClientCapabilityV1,
-    PluggableRuntime, WasiEnv, WasiEnvBuilder,
+use eyre::{eyre, Result};
+use tokio::runtime::Handle;
+
+use crate::cli::{
+    CacheCommand, CacheOptions, Cli, Command, CompileArgs, GuestArgs, InspectArgs, InvokeArgs,
+    ReactorArgs, RunArgs,
 };
 
-fn create_wasi_env(
-    capabilities: Capabilities,
-    runtime_handle: tokio::runtime::Handle,
-) -> eyre::Result<(WasiEnvBuilder, Pipe, Pipe, Pipe)> {
-    let (stdin_tx, stdin_rx) = Pipe::channel();
-    let (stdout, stdout_rx) = Pipe::channel();
-    let (stderr, stderr_rx) = Pipe::channel();
-
-    let runtime = PluggableRuntime::new(Arc::new(
-        wasmer_wasix::runtime::task_manager::tokio::TokioTaskManager::new(runtime_handle),
-    ));
-
-    let builder = WasiEnv::builder("nor2")
-        .runtime(Arc::new(runtime))
-        .capabilities(capabilities)
-        .stdin(Box::new(stdin_rx))
-        .stdout(Box::new(stdout))
-        .stderr(Box::new(stderr))
-        .env("RUST_LOG", "trace")
-        .env("WASMER_BACKTRACE", "1")
-        .env("RUST_BACKTRACE", "wasmer_wasix=trace");
-
-    Ok((builder, stdin_tx, stdout_rx, stderr_rx))
+/// Prints the library's messages, like cache hits or stubbed imports, on stderr.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.target().starts_with("cs_runtime_example")
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}", record.args());
+        }
+    }
+
+    fn flush(&self) {}
 }
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
+    let cli = Cli::parse();
+
+    log::set_logger(&StderrLogger)?;
+    log::set_max_level(log::LevelFilter::Info);
+
     let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()?;
 
     let handle = runtime.handle().clone();
 
-    start(handle)?;
+    let exit_code = match cli.command {
+        Command::Run(args) => start(&args, handle)?.process_exit_code(),
+        Command::Invoke(args) => invoke(&args, handle)?.process_exit_code(),
+        Command::Reactor(args) => {
+            reactor(&args, handle)?;
+            ExitCode::SUCCESS
+        }
+        Command::Inspect(args) => {
+            inspect(&args)?;
+            ExitCode::SUCCESS
+        }
+        Command::Compile(args) => {
+            compile(&args)?;
+            ExitCode::SUCCESS
+        }
+        Command::Cache(CacheCommand::Clean(options)) => {
+            let cache = open_cache(&options)?;
+            let (count, freed) = cache.clean()?;
+            println!(
+                "Removed {count} cached modules ({freed} bytes) from {}",
+                cache.dir().display()
+            );
+            ExitCode::SUCCESS
+        }
+    };
 
-    Ok(())
+    // A forwarder blocked on the host's stdin can't be cancelled, don't wait for it.
+    runtime.shutdown_background();
+
+    Ok(exit_code)
 }
 
-fn start(handle: Handle) -> Result<()> {
-    let mut features = Features::default();
-    features.reference_types(true);
-    features.multi_memory(true);
-    features.module_linking(true);
-    features.tail_call(true);
-    features.threads(true);
-
-    let engine = EngineBuilder::new(Cranelift::default())
-        .set_features(Some(features))
-        .engine();
-
-    let capabilities = Capabilities {
-        insecure_allow_all: true,
-        http_client: HttpClientCapabilityV1::new_allow_all(),
-        threading: CapabilityThreadingV1::default(),
-    };
+fn cache_config(options: &CacheOptions) -> CacheConfig {
+    CacheConfig {
+        dir: options.cache_dir.clone().unwrap_or_else(default_cache_dir),
+        max_size: options.cache_max_size,
+    }
+}
 
-    let (builder, _stdin_tx, mut stdout_rx, mut stderr_rx) = create_wasi_env(capabilities, handle)?;
+fn open_cache(options: &CacheOptions) -> Result<ModuleCache> {
+    let config = cache_config(options);
+    Ok(ModuleCache::open(config.dir, config.max_size)?)
+}
 
-    let mut store = Store::new(engine);
-    let module = Module::new(&store, include_bytes!("../cswasi.wasm"))?;
+/// The guest's policy. `--insecure-allow-all` wins over a policy next to the module,
+/// since it is the one the user asked for.
+fn load_policy(module: Option<&Path>, args: &GuestArgs) -> Result<Option<Policy>> {
+    let discovered = module.and_then(Policy::discover);
+    if args.insecure_allow_all {
+        match discovered {
+            Some(path) => eprintln!(
+                "Ignoring policy {} because of --insecure-allow-all, the guest has every capability",
+                path.display()
+            ),
+            None => eprintln!("Running without a policy, the guest has every capability"),
+        }
+        return Ok(None);
+    }
+
+    match args.policy.clone().or(discovered) {
+        Some(path) => {
+            let policy = Policy::load(&path)?;
+            eprintln!("Loaded policy {}: {policy}", path.display());
+            Ok(Some(policy))
+        }
+        None => Err(eyre!(
+            "no capability policy for the guest, pass --policy <FILE> or --insecure-allow-all"
+        )),
+    }
+}
 
-    let mut wasi_env = builder.finalize(&mut store)?;
+/// Configures the guest from the command line.
+fn guest_runtime(module: Option<&Path>, args: &GuestArgs) -> Result<CsGuestRuntime> {
+    let source = match module {
+        Some(path) if args.precompiled => ModuleSource::Artifact(path.to_path_buf()),
+        Some(path) => ModuleSource::File(path.to_path_buf()),
+        None if args.precompiled => {
+            return Err(eyre!("--precompiled needs the path of an artifact"));
+        }
+        None => ModuleSource::Bundled,
+    };
 
-    let imports = imports! {
-      "rust" => {
-        "wasmImportFloat32Param" => Function::new(&mut store, FunctionType::new(vec![Type::F32], vec![]), |args| {
-            println!("Hello from rust {args:#?}");
-            Ok(vec![])
-        })
-      }
+    let mut guest = CsGuestRuntime::new(source).sample_imports();
+    guest = match load_policy(module, args)? {
+        Some(policy) => guest.policy(&policy),
+        None => guest.capabilities(insecure_capabilities()),
     };
-    let extend: Vec<((String, String), Extern)> = imports.into_iter().collect();
+    if let Some(cwd) = &args.cwd {
+        guest = guest.current_dir(cwd);
+    }
+    if !args.no_cache {
+        guest = guest.cache(cache_config(&args.cache));
+    }
+    if let Some(mode) = args.http.mode() {
+        guest = guest.http(mode);
+    }
+    if args.loopback_net {
+        guest = guest.network(LoopbackNetworking::new());
+    }
 
-    let mut import_object = wasi_env.import_object_for_all_wasi_versions(&mut store, &module)?;
-    import_object.extend(extend);
+    Ok(guest
+        .backend(args.backend)
+        .fallback(!args.no_fallback)
+        .feature_overrides(args.features.overrides())
+        .program_name(&args.program_name)
+        .profile(args.profile.choice)
+        .stub_missing_imports(args.stub_missing_imports)
+        .envs(args.envs.iter().cloned())
+        .mounts(args.fs.mounts())
+        .overlay(args.fs.overlay)
+        .stdin(args.stdin.clone())
+        .stdout(args.stdio.stdout())
+        .stderr(args.stdio.stderr())
+        .limits(args.limits.resources())
+        .fuel(args.limits.fuel)
+        .timeout(args.limits.timeout))
+}
 
-    let instance = Instance::new(&mut store, &module, &import_object)?;
+fn start(args: &RunArgs, handle: Handle) -> Result<Outcome> {
+    let report = guest_runtime(args.module.as_deref(), &args.guest)?
+        .args(&args.args)
+        .instantiate(&handle)?
+        .run()?;
 
-    wasi_env.data(&store).thread.set_status_running();
+    if let Some(remaining) = report.remaining_fuel {
+        eprintln!("Remaining fuel: {remaining}");
+    }
+    for (name, pages) in &report.memory {
+        eprintln!(
+            "Peak memory `{name}`: {} pages ({} MiB)",
+            pages.0,
+            pages.bytes().0 / (1024 * 1024)
+        );
+    }
 
-    wasi_env.initialize(&mut store, instance.clone())?;
+    println!("{}", report.outcome);
+    if let Some(backtrace) = &report.backtrace {
+        print!("{backtrace}");
+    }
 
-    let start_func = instance.exports.get_function("_start").unwrap();
+    Ok(report.outcome)
+}
 
-    let result = start_func.call(&mut store, &[]);
+fn invoke(args: &InvokeArgs, handle: Handle) -> Result<Outcome> {
+    let mut instance = guest_runtime(args.module.as_deref(), &args.guest)?.instantiate(&handle)?;
 
-    wasi_env.cleanup(&mut store, None);
+    let result = instance
+        .initialize()
+        .and_then(|_| instance.invoke(&args.export, &args.values));
+    let backtrace = match &result {
+        Err(Error::Guest(Outcome::Trapped { error, .. })) => {
+            Some(Symbolizer::new(instance.module_bytes()).report(error.trace()))
+        }
+        _ => None,
+    };
+    instance.finish()?;
 
-    let mut std_out = String::default();
-    if stdout_rx.read_to_string(&mut std_out)? != 0 {
-        println!("Std out: {std_out}");
+    let outcome = match result {
+        Ok(values) => {
+            for value in values.iter() {
+                println!("{}", format_value(value));
+            }
+            Outcome::Exited(0)
+        }
+        Err(Error::Guest(outcome)) => outcome,
+        Err(err) => return Err(err.into()),
+    };
+
+    if outcome.exit_code() != 0 {
+        println!("{outcome}");
+    }
+    if let Some(backtrace) = &backtrace {
+        print!("{backtrace}");
+    }
+
+    Ok(outcome)
+}
+
+fn inspect(args: &InspectArgs) -> Result<()> {
+    let report = CsGuestRuntime::new(ModuleSource::File(args.module.clone()))
+        .sample_imports()
+        .backend(args.backend)
+        .feature_overrides(args.features.overrides())
+        .inspect()?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{report}");
     }
+    Ok(())
+}
 
-    let mut std_err = String::default();
-    if stderr_rx.read_to_string(&mut std_err)? != 0 {
-        println!("Std err: {std_err}");
+fn compile(args: &CompileArgs) -> Result<()> {
+    let header = CsGuestRuntime::new(ModuleSource::File(args.module.clone()))
+        .backend(args.backend)
+        .feature_overrides(args.features.overrides())
+        .profile(args.profile.choice)
+        .limits(args.limits.resources())
+        .fuel(args.limits.fuel)
+        .timeout(args.limits.timeout)
+        .precompile(&args.output)?;
+    println!("Wrote {}: {header}", args.output.display());
+    Ok(())
+}
+
+/// Serves export calls read from stdin until end-of-file, then shuts the guest down.
+fn reactor(args: &ReactorArgs, handle: Handle) -> Result<()> {
+    if args.guest.stdin == StdinSource::Inherit {
+        return Err(eyre!(
+            "the reactor reads calls from stdin, it can't be forwarded to the guest too"
+        ));
     }
 
-    match result {
-        Ok(_) => println!("Success"),
-        Err(err) => {
-            println!("Runtime Error: {err}");
-            err.trace();
+    let mut reactor = guest_runtime(Some(&args.module), &args.guest)?.reactor(&handle)?;
+    let exports: Vec<String> = reactor
+        .functions()
+        .iter()
+        .map(|(name, ty)| format_signature(name, ty))
+        .collect();
+    eprintln!("Reactor ready, exports:\n  {}", exports.join("\n  "));
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        let Some(export) = words.next() else {
+            continue;
+        };
+        let values: Vec<String> = words.map(str::to_string).collect();
+
+        match reactor.invoke(export, &values) {
+            Ok(results) => {
+                let results: Vec<String> = results.iter().map(format_value).collect();
+                println!("{}", results.join(" "));
+            }
+            Err(Error::Guest(outcome)) => {
+                println!("{outcome}");
+                if let Outcome::Trapped { error, .. } = &outcome {
+                    print!(
+                        "{}",
+                        Symbolizer::new(reactor.module_bytes()).report(error.trace())
+                    );
+                }
+            }
+            Err(err) => println!("Error: {err}"),
         }
     }
 
+    reactor.shutdown()?;
     Ok(())
 }
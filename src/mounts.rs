@@ -0,0 +1,308 @@
+use std::{
+    fs,
+    io::Read,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use tokio::io::AsyncWriteExt;
+use virtual_fs::{host_fs, mem_fs, FileSystem, TmpFileSystem};
+use wasmer_wasix::WasiEnvBuilder;
+
+use crate::error::{Error, Result};
+
+/// Most a host directory mounted with `overlay` or seeding an in-memory mount may
+/// hold, since all of it is copied into memory. Symlinks in it are skipped.
+pub const OVERLAY_MAX_SIZE: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MountSource {
+    /// A directory on the host.
+    Host(PathBuf),
+    /// An in-memory filesystem, optionally seeded from a directory or tarball.
+    Memory { seed: Option<PathBuf> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    pub guest: PathBuf,
+    pub source: MountSource,
+    pub read_only: bool,
+}
+
+/// Parses `GUEST:HOST[:ro|:rw]`.
+pub fn parse_mapdir(s: &str) -> Result<Mount> {
+    let (guest, rest) = s.split_once(':').ok_or_else(|| {
+        Error::InvalidArgument(format!("expected GUEST:HOST[:ro|:rw], got `{s}`"))
+    })?;
+    let (host, read_only) = match rest.rsplit_once(':') {
+        Some((host, "ro")) => (host, true),
+        Some((host, "rw")) => (host, false),
+        _ => (rest, false),
+    };
+    Ok(Mount {
+        guest: guest_path(guest)?,
+        source: MountSource::Host(PathBuf::from(host)),
+        read_only,
+    })
+}
+
+/// Parses `GUEST[:SEED]`, where the seed is a directory or a `.tar`/`.tar.gz` file.
+pub fn parse_memdir(s: &str) -> Result<Mount> {
+    let (guest, seed) = match s.split_once(':') {
+        Some((guest, seed)) => (guest, Some(PathBuf::from(seed))),
+        None => (s, None),
+    };
+    Ok(Mount {
+        guest: guest_path(guest)?,
+        source: MountSource::Memory { seed },
+        read_only: false,
+    })
+}
+
+/// Parses a path in the guest filesystem.
+pub fn guest_path(s: &str) -> Result<PathBuf> {
+    let path = Path::new(s);
+    if !path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+        return Err(Error::InvalidArgument(format!(
+            "guest path `{s}` must be absolute and must not contain `..`"
+        )));
+    }
+    Ok(path.to_path_buf())
+}
+
+/// Mounts everything into a sandbox filesystem and preopens each mount for the guest.
+///
+/// With `overlay`, each host directory is mounted as an in-memory snapshot taken
+/// when the guest starts: guest writes never reach the host, and host changes made
+/// afterwards aren't seen. Directories over [`OVERLAY_MAX_SIZE`] are refused and
+/// symlinks are left out. This is a copy, not a copy-on-write layer.
+///
+/// `cwd` is preopened again as `.`, which is what wasi-libc resolves relative
+/// paths against; outside every mount it is an empty in-memory directory.
+pub fn mount_all(
+    mut builder: WasiEnvBuilder,
+    mounts: &[Mount],
+    overlay: bool,
+    cwd: Option<&Path>,
+) -> Result<WasiEnvBuilder> {
+    if mounts.is_empty() && cwd.is_none() {
+        return Ok(builder);
+    }
+
+    let root = TmpFileSystem::new();
+    for mount in mounts {
+        if let Some(parent) = mount.guest.parent() {
+            create_dir_all(&root, parent)?;
+        }
+
+        let (fs, target): (Arc<dyn FileSystem + Send + Sync>, PathBuf) = match &mount.source {
+            MountSource::Host(host) if overlay => {
+                let memory = mem_fs::FileSystem::default();
+                seed_from_dir(&memory, host)?;
+                (Arc::new(memory), PathBuf::from("/"))
+            }
+            MountSource::Host(host) => {
+                let host = host.canonicalize().map_err(Error::file("mount", host))?;
+                (Arc::new(host_fs::FileSystem), host)
+            }
+            MountSource::Memory { seed } => {
+                let memory = mem_fs::FileSystem::default();
+                match seed {
+                    Some(seed) if seed.is_dir() => seed_from_dir(&memory, seed)?,
+                    Some(seed) => seed_from_tarball(&memory, seed)?,
+                    None => {}
+                }
+                (Arc::new(memory), PathBuf::from("/"))
+            }
+        };
+
+        root.mount(mount.guest.clone(), &fs, target)
+            .map_err(Error::fs("mount", &mount.guest))?;
+
+        let writable = !mount.read_only;
+        builder = builder.preopen_build(|p| {
+            p.directory(&mount.guest)
+                .alias(&mount.guest.to_string_lossy())
+                .read(true)
+                .write(writable)
+                .create(writable)
+        })?;
+    }
+
+    if let Some(cwd) = cwd {
+        create_dir_all(&root, cwd)?;
+        let writable = !mounts
+            .iter()
+            .filter(|mount| cwd.starts_with(&mount.guest))
+            .max_by_key(|mount| mount.guest.components().count())
+            .is_some_and(|mount| mount.read_only);
+        builder = builder.preopen_build(|p| {
+            p.directory(cwd)
+                .alias(".")
+                .read(true)
+                .write(writable)
+                .create(writable)
+        })?;
+    }
+
+    Ok(builder.fs(Box::new(root)))
+}
+
+fn create_dir_all(fs: &dyn FileSystem, path: &Path) -> Result<()> {
+    let mut current = PathBuf::from("/");
+    for component in path.components() {
+        if let Component::Normal(name) = component {
+            current.push(name);
+            if fs.metadata(&current).is_err() {
+                fs.create_dir(&current)
+                    .map_err(Error::fs("create", &current))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_file(fs: &dyn FileSystem, path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        create_dir_all(fs, parent)?;
+    }
+    let mut file = fs
+        .new_open_options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .map_err(Error::fs("create", path))?;
+    // In-memory writes complete immediately and don't need the tokio runtime.
+    futures::executor::block_on(file.write_all(contents))?;
+    Ok(())
+}
+
+/// A file or directory of a host directory being copied into memory.
+struct SnapshotEntry {
+    /// Path relative to the copied directory.
+    relative: PathBuf,
+    is_dir: bool,
+}
+
+/// Lists what copying `dir` into memory copies, refusing directories over
+/// [`OVERLAY_MAX_SIZE`].
+///
+/// Symlinks are skipped, not followed: they may lead out of the directory or back
+/// into it, and the guest's copy couldn't keep them as links anyway.
+fn snapshot_entries(dir: &Path) -> Result<Vec<SnapshotEntry>> {
+    let mut entries = Vec::new();
+    let mut size = 0u64;
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        let host_dir = dir.join(&relative);
+        for entry in fs::read_dir(&host_dir).map_err(Error::file("read", &host_dir))? {
+            let entry = entry?;
+            let relative = relative.join(entry.file_name());
+            let file_type = entry.file_type()?;
+            if file_type.is_symlink() {
+                log::warn!(
+                    "Not copying the symlink {} into the guest filesystem",
+                    entry.path().display()
+                );
+                continue;
+            }
+
+            if file_type.is_dir() {
+                pending.push(relative.clone());
+            } else {
+                size += entry.metadata()?.len();
+            }
+            if size > OVERLAY_MAX_SIZE {
+                return Err(Error::Limit(format!(
+                    "{} holds more than the {} MiB a mount may copy into memory",
+                    dir.display(),
+                    OVERLAY_MAX_SIZE / (1024 * 1024)
+                )));
+            }
+            entries.push(SnapshotEntry {
+                relative,
+                is_dir: file_type.is_dir(),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+fn seed_from_dir(fs: &dyn FileSystem, dir: &Path) -> Result<()> {
+    // Parents are listed before their contents.
+    for entry in snapshot_entries(dir)? {
+        let guest = Path::new("/").join(&entry.relative);
+        if entry.is_dir {
+            create_dir_all(fs, &guest)?;
+        } else {
+            let host = dir.join(&entry.relative);
+            let contents = fs::read(&host).map_err(Error::file("read", &host))?;
+            write_file(fs, &guest, &contents)?;
+        }
+    }
+    Ok(())
+}
+
+fn seed_from_tarball(fs: &dyn FileSystem, path: &Path) -> Result<()> {
+    let file = fs::File::open(path).map_err(Error::file("open", path))?;
+    let name = path.to_string_lossy();
+    let reader: Box<dyn Read> = if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Box::new(flate2::read::GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let relative = entry.path()?.into_owned();
+        if relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(Error::InvalidArgument(format!(
+                "{} contains an unsafe path: {}",
+                path.display(),
+                relative.display()
+            )));
+        }
+        let guest = Path::new("/").join(&relative);
+        let kind = entry.header().entry_type();
+        if kind.is_dir() {
+            create_dir_all(fs, &guest)?;
+        } else if kind.is_file() {
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            write_file(fs, &guest, &contents)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn snapshots_skip_symlinks() {
+        let dir = std::env::temp_dir().join(format!("cs-snapshot-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("file"), "contents").unwrap();
+        fs::write(dir.join("sub/nested"), "nested").unwrap();
+        std::os::unix::fs::symlink(dir.join("file"), dir.join("file-link")).unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("sub/loop")).unwrap();
+
+        let memory = mem_fs::FileSystem::default();
+        let seeded = seed_from_dir(&memory, &dir);
+        fs::remove_dir_all(&dir).unwrap();
+        seeded.unwrap();
+
+        assert!(memory.metadata(Path::new("/file")).unwrap().is_file());
+        assert!(memory.metadata(Path::new("/sub/nested")).unwrap().is_file());
+        assert!(memory.metadata(Path::new("/file-link")).is_err());
+        assert!(memory.metadata(Path::new("/sub/loop")).is_err());
+    }
+}
@@ -0,0 +1,787 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    mem::MaybeUninit,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use virtual_net::{
+    IpCidr, NetworkError, SocketStatus, VirtualConnectedSocket, VirtualNetworking, VirtualSocket,
+    VirtualTcpListener, VirtualTcpSocket,
+};
+
+/// First port handed out to sockets bound to port 0.
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+
+/// Bytes buffered per direction before writers are told to wait.
+const BUFFER_SIZE: usize = 256 * 1024;
+
+/// An in-process network where guests can only reach loopback addresses.
+///
+/// Nothing is ever bound on the host: guest listeners are visible to other guests
+/// sharing the network and to the host through [`LoopbackNetworking::connect`], and
+/// the host can accept guest connections with [`LoopbackNetworking::listen`].
+#[derive(Debug, Clone, Default)]
+pub struct LoopbackNetworking {
+    inner: Arc<Registry>,
+}
+
+type Listeners = HashMap<SocketAddr, Arc<Mutex<Backlog>>>;
+
+#[derive(Debug, Default)]
+struct Registry {
+    listeners: Mutex<Listeners>,
+    next_port: AtomicU16,
+}
+
+#[derive(Debug, Default)]
+struct Backlog {
+    pending: VecDeque<(Connection, SocketAddr)>,
+    waker: Option<Waker>,
+}
+
+impl LoopbackNetworking {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accepts connections that guests make to `addr`.
+    pub fn listen(&self, addr: SocketAddr) -> Result<HostListener, NetworkError> {
+        let (addr, backlog) = self.inner.register(addr)?;
+        Ok(HostListener {
+            addr,
+            backlog,
+            registry: self.inner.clone(),
+        })
+    }
+
+    /// Opens a connection to a guest listening on `addr`.
+    pub fn connect(&self, addr: SocketAddr) -> Result<LoopbackStream, NetworkError> {
+        let local = self.inner.ephemeral(loopback_ip(addr.ip())?)?;
+        let connection = self.inner.connect(local, addr)?;
+        Ok(LoopbackStream { connection })
+    }
+}
+
+impl Registry {
+    fn ephemeral(&self, ip: IpAddr) -> Result<SocketAddr, NetworkError> {
+        self.free_port(&self.listeners.lock().unwrap(), ip)
+    }
+
+    /// The next ephemeral port on `ip` that nothing listens on.
+    fn free_port(&self, listeners: &Listeners, ip: IpAddr) -> Result<SocketAddr, NetworkError> {
+        let ports = u16::MAX - FIRST_EPHEMERAL_PORT + 1;
+        (0..ports)
+            .map(|_| {
+                let offset = self.next_port.fetch_add(1, Ordering::Relaxed) % ports;
+                SocketAddr::new(ip, FIRST_EPHEMERAL_PORT + offset)
+            })
+            .find(|addr| !listeners.contains_key(addr))
+            .ok_or(NetworkError::AddressInUse)
+    }
+
+    fn register(
+        &self,
+        addr: SocketAddr,
+    ) -> Result<(SocketAddr, Arc<Mutex<Backlog>>), NetworkError> {
+        let ip = loopback_ip(addr.ip())?;
+        let mut listeners = self.listeners.lock().unwrap();
+        let addr = match addr.port() {
+            0 => self.free_port(&listeners, ip)?,
+            port => SocketAddr::new(ip, port),
+        };
+        if listeners.contains_key(&addr) {
+            return Err(NetworkError::AddressInUse);
+        }
+        let backlog = Arc::new(Mutex::new(Backlog::default()));
+        listeners.insert(addr, backlog.clone());
+        Ok((addr, backlog))
+    }
+
+    fn unregister(&self, addr: SocketAddr) {
+        self.listeners.lock().unwrap().remove(&addr);
+    }
+
+    fn connect(&self, local: SocketAddr, peer: SocketAddr) -> Result<Connection, NetworkError> {
+        let peer = SocketAddr::new(loopback_ip(peer.ip())?, peer.port());
+        let backlog = self
+            .listeners
+            .lock()
+            .unwrap()
+            .get(&peer)
+            .cloned()
+            .ok_or(NetworkError::ConnectionRefused)?;
+
+        let (ours, theirs) = Connection::pair(local, peer);
+        let mut backlog = backlog.lock().unwrap();
+        backlog.pending.push_back((theirs, local));
+        if let Some(waker) = backlog.waker.take() {
+            waker.wake();
+        }
+        Ok(ours)
+    }
+}
+
+/// Maps wildcard addresses to loopback and rejects anything else.
+fn loopback_ip(ip: IpAddr) -> Result<IpAddr, NetworkError> {
+    match ip {
+        IpAddr::V4(ip) if ip.is_unspecified() => Ok(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+        IpAddr::V6(ip) if ip.is_unspecified() => Ok(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+        ip if ip.is_loopback() => Ok(ip),
+        _ => Err(NetworkError::PermissionDenied),
+    }
+}
+
+#[async_trait::async_trait]
+impl VirtualNetworking for LoopbackNetworking {
+    fn ip_list(&self) -> virtual_net::Result<Vec<IpCidr>> {
+        Ok(vec![
+            IpCidr {
+                ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                prefix: 8,
+            },
+            IpCidr {
+                ip: IpAddr::V6(Ipv6Addr::LOCALHOST),
+                prefix: 128,
+            },
+        ])
+    }
+
+    async fn listen_tcp(
+        &self,
+        addr: SocketAddr,
+        _only_v6: bool,
+        _reuse_port: bool,
+        _reuse_addr: bool,
+    ) -> virtual_net::Result<Box<dyn VirtualTcpListener + Sync>> {
+        let (addr, backlog) = self.inner.register(addr)?;
+        Ok(Box::new(GuestListener {
+            addr,
+            backlog,
+            registry: self.inner.clone(),
+            ttl: 64,
+        }))
+    }
+
+    async fn connect_tcp(
+        &self,
+        addr: SocketAddr,
+        peer: SocketAddr,
+    ) -> virtual_net::Result<Box<dyn VirtualTcpSocket + Sync>> {
+        let local = match addr.port() {
+            0 => self.inner.ephemeral(loopback_ip(addr.ip())?)?,
+            _ => SocketAddr::new(loopback_ip(addr.ip())?, addr.port()),
+        };
+        let connection = self.inner.connect(local, peer)?;
+        Ok(Box::new(GuestSocket::new(connection)))
+    }
+
+    async fn resolve(
+        &self,
+        host: &str,
+        _port: Option<u16>,
+        _dns_server: Option<IpAddr>,
+    ) -> virtual_net::Result<Vec<IpAddr>> {
+        match host {
+            "localhost" => Ok(vec![
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ]),
+            host => match host.parse::<IpAddr>() {
+                Ok(ip) if ip.is_loopback() => Ok(vec![ip]),
+                _ => Err(NetworkError::AddressNotAvailable),
+            },
+        }
+    }
+}
+
+/// One direction of a connection.
+#[derive(Debug, Default)]
+struct Channel {
+    data: VecDeque<u8>,
+    closed: bool,
+    reader: Option<Waker>,
+    writer: Option<Waker>,
+}
+
+/// One end of an in-memory TCP connection.
+#[derive(Debug)]
+struct Connection {
+    local: SocketAddr,
+    peer: SocketAddr,
+    rx: Arc<Mutex<Channel>>,
+    tx: Arc<Mutex<Channel>>,
+}
+
+impl Connection {
+    fn pair(a: SocketAddr, b: SocketAddr) -> (Self, Self) {
+        let a_to_b = Arc::new(Mutex::new(Channel::default()));
+        let b_to_a = Arc::new(Mutex::new(Channel::default()));
+        (
+            Connection {
+                local: a,
+                peer: b,
+                rx: b_to_a.clone(),
+                tx: a_to_b.clone(),
+            },
+            Connection {
+                local: b,
+                peer: a,
+                rx: a_to_b,
+                tx: b_to_a,
+            },
+        )
+    }
+
+    fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [MaybeUninit<u8>]) -> Poll<usize> {
+        let mut rx = self.rx.lock().unwrap();
+        if rx.data.is_empty() {
+            if rx.closed {
+                return Poll::Ready(0);
+            }
+            rx.reader = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let n = buf.len().min(rx.data.len());
+        for (slot, byte) in buf.iter_mut().zip(rx.data.drain(..n)) {
+            slot.write(byte);
+        }
+        if let Some(writer) = rx.writer.take() {
+            writer.wake();
+        }
+        Poll::Ready(n)
+    }
+
+    fn poll_write(&self, cx: &mut Context<'_>, data: &[u8]) -> Poll<io::Result<usize>> {
+        let mut tx = self.tx.lock().unwrap();
+        if tx.closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        let space = BUFFER_SIZE.saturating_sub(tx.data.len());
+        if space == 0 {
+            tx.writer = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let n = data.len().min(space);
+        tx.data.extend(&data[..n]);
+        if let Some(reader) = tx.reader.take() {
+            reader.wake();
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn readable(&self) -> usize {
+        self.rx.lock().unwrap().data.len()
+    }
+
+    fn writable(&self) -> usize {
+        BUFFER_SIZE.saturating_sub(self.tx.lock().unwrap().data.len())
+    }
+
+    fn shutdown(&self, how: Shutdown) {
+        let close = |channel: &Arc<Mutex<Channel>>| {
+            let mut channel = channel.lock().unwrap();
+            channel.closed = true;
+            for waker in [channel.reader.take(), channel.writer.take()]
+                .into_iter()
+                .flatten()
+            {
+                waker.wake();
+            }
+        };
+        if matches!(how, Shutdown::Write | Shutdown::Both) {
+            close(&self.tx);
+        }
+        if matches!(how, Shutdown::Read | Shutdown::Both) {
+            close(&self.rx);
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.rx.lock().unwrap().closed && self.tx.lock().unwrap().closed
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.shutdown(Shutdown::Both);
+    }
+}
+
+fn poll_accept(backlog: &Mutex<Backlog>, cx: &mut Context<'_>) -> Poll<(Connection, SocketAddr)> {
+    let mut backlog = backlog.lock().unwrap();
+    match backlog.pending.pop_front() {
+        Some(accepted) => Poll::Ready(accepted),
+        None => {
+            backlog.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// A guest's listening socket.
+#[derive(Debug)]
+struct GuestListener {
+    addr: SocketAddr,
+    backlog: Arc<Mutex<Backlog>>,
+    registry: Arc<Registry>,
+    ttl: u8,
+}
+
+impl Drop for GuestListener {
+    fn drop(&mut self) {
+        self.registry.unregister(self.addr);
+    }
+}
+
+impl VirtualTcpListener for GuestListener {
+    fn try_accept(
+        &mut self,
+    ) -> Option<virtual_net::Result<(Box<dyn VirtualTcpSocket + Sync>, SocketAddr)>> {
+        let (connection, peer) = self.backlog.lock().unwrap().pending.pop_front()?;
+        Some(Ok((Box::new(GuestSocket::new(connection)), peer)))
+    }
+
+    fn poll_accept(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<virtual_net::Result<(Box<dyn VirtualTcpSocket + Sync>, SocketAddr)>> {
+        poll_accept(&self.backlog, cx).map(|(connection, peer)| {
+            Ok((
+                Box::new(GuestSocket::new(connection)) as Box<dyn VirtualTcpSocket + Sync>,
+                peer,
+            ))
+        })
+    }
+
+    fn poll_accept_ready(&mut self, cx: &mut Context<'_>) -> Poll<virtual_net::Result<usize>> {
+        let mut backlog = self.backlog.lock().unwrap();
+        match backlog.pending.len() {
+            0 => {
+                backlog.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            n => Poll::Ready(Ok(n)),
+        }
+    }
+
+    fn addr_local(&self) -> virtual_net::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn set_ttl(&mut self, ttl: u8) -> virtual_net::Result<()> {
+        self.ttl = ttl;
+        Ok(())
+    }
+
+    fn ttl(&self) -> virtual_net::Result<u8> {
+        Ok(self.ttl)
+    }
+}
+
+/// A guest's end of a connection.
+#[derive(Debug)]
+struct GuestSocket {
+    connection: Connection,
+    ttl: u32,
+    nodelay: bool,
+    linger: Option<Duration>,
+}
+
+impl GuestSocket {
+    fn new(connection: Connection) -> Self {
+        Self {
+            connection,
+            ttl: 64,
+            nodelay: false,
+            linger: None,
+        }
+    }
+}
+
+impl VirtualSocket for GuestSocket {
+    fn set_ttl(&mut self, ttl: u32) -> virtual_net::Result<()> {
+        self.ttl = ttl;
+        Ok(())
+    }
+
+    fn ttl(&self) -> virtual_net::Result<u32> {
+        Ok(self.ttl)
+    }
+
+    fn addr_local(&self) -> virtual_net::Result<SocketAddr> {
+        Ok(self.connection.local)
+    }
+
+    fn status(&self) -> virtual_net::Result<SocketStatus> {
+        Ok(match self.connection.is_closed() {
+            true => SocketStatus::Closed,
+            false => SocketStatus::Opened,
+        })
+    }
+
+    fn poll_read_ready(&mut self, cx: &mut Context<'_>) -> Poll<virtual_net::Result<usize>> {
+        let mut rx = self.connection.rx.lock().unwrap();
+        if rx.data.is_empty() && !rx.closed {
+            rx.reader = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        Poll::Ready(Ok(rx.data.len()))
+    }
+
+    fn poll_write_ready(&mut self, cx: &mut Context<'_>) -> Poll<virtual_net::Result<usize>> {
+        let mut tx = self.connection.tx.lock().unwrap();
+        if tx.closed {
+            return Poll::Ready(Err(NetworkError::ConnectionReset));
+        }
+        match BUFFER_SIZE.saturating_sub(tx.data.len()) {
+            0 => {
+                tx.writer = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            space => Poll::Ready(Ok(space)),
+        }
+    }
+}
+
+impl VirtualConnectedSocket for GuestSocket {
+    fn set_linger(&mut self, linger: Option<Duration>) -> virtual_net::Result<()> {
+        self.linger = linger;
+        Ok(())
+    }
+
+    fn linger(&self) -> virtual_net::Result<Option<Duration>> {
+        Ok(self.linger)
+    }
+
+    fn try_send(&mut self, data: &[u8]) -> virtual_net::Result<usize> {
+        let waker = noop_waker();
+        match self
+            .connection
+            .poll_write(&mut Context::from_waker(&waker), data)
+        {
+            Poll::Ready(Ok(n)) => Ok(n),
+            Poll::Ready(Err(_)) => Err(NetworkError::ConnectionReset),
+            Poll::Pending => Err(NetworkError::WouldBlock),
+        }
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>, data: &[u8]) -> Poll<virtual_net::Result<usize>> {
+        self.connection
+            .poll_write(cx, data)
+            .map(|result| result.map_err(|_| NetworkError::ConnectionReset))
+    }
+
+    fn poll_flush(&mut self, _cx: &mut Context<'_>) -> Poll<virtual_net::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn close(&mut self) -> virtual_net::Result<()> {
+        self.connection.shutdown(Shutdown::Both);
+        Ok(())
+    }
+
+    fn poll_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut [MaybeUninit<u8>],
+    ) -> Poll<virtual_net::Result<usize>> {
+        self.connection.poll_read(cx, buf).map(Ok)
+    }
+
+    fn try_recv(&mut self, buf: &mut [MaybeUninit<u8>]) -> virtual_net::Result<usize> {
+        let waker = noop_waker();
+        match self
+            .connection
+            .poll_read(&mut Context::from_waker(&waker), buf)
+        {
+            Poll::Ready(n) => Ok(n),
+            Poll::Pending => Err(NetworkError::WouldBlock),
+        }
+    }
+}
+
+impl VirtualTcpSocket for GuestSocket {
+    fn set_recv_buf_size(&mut self, _size: usize) -> virtual_net::Result<()> {
+        Ok(())
+    }
+
+    fn recv_buf_size(&self) -> virtual_net::Result<usize> {
+        Ok(BUFFER_SIZE)
+    }
+
+    fn set_send_buf_size(&mut self, _size: usize) -> virtual_net::Result<()> {
+        Ok(())
+    }
+
+    fn send_buf_size(&self) -> virtual_net::Result<usize> {
+        Ok(BUFFER_SIZE)
+    }
+
+    fn set_nodelay(&mut self, nodelay: bool) -> virtual_net::Result<()> {
+        self.nodelay = nodelay;
+        Ok(())
+    }
+
+    fn nodelay(&self) -> virtual_net::Result<bool> {
+        Ok(self.nodelay)
+    }
+
+    fn addr_peer(&self) -> virtual_net::Result<SocketAddr> {
+        Ok(self.connection.peer)
+    }
+
+    fn shutdown(&mut self, how: Shutdown) -> virtual_net::Result<()> {
+        self.connection.shutdown(how);
+        Ok(())
+    }
+
+    fn is_closed(&self) -> bool {
+        self.connection.is_closed()
+    }
+}
+
+/// A waker for the non-blocking `try_*` calls, which never wait.
+fn noop_waker() -> Waker {
+    struct Noop;
+    impl std::task::Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+    Waker::from(Arc::new(Noop))
+}
+
+/// Accepts the connections guests make to one loopback address.
+#[derive(Debug)]
+pub struct HostListener {
+    addr: SocketAddr,
+    backlog: Arc<Mutex<Backlog>>,
+    registry: Arc<Registry>,
+}
+
+impl HostListener {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub async fn accept(&self) -> (LoopbackStream, SocketAddr) {
+        let (connection, peer) = std::future::poll_fn(|cx| poll_accept(&self.backlog, cx)).await;
+        (LoopbackStream { connection }, peer)
+    }
+}
+
+impl Drop for HostListener {
+    fn drop(&mut self) {
+        self.registry.unregister(self.addr);
+    }
+}
+
+/// The host's end of a connection with a guest.
+#[derive(Debug)]
+pub struct LoopbackStream {
+    connection: Connection,
+}
+
+impl LoopbackStream {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.connection.local
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.connection.peer
+    }
+
+    /// Bytes that can be read without waiting.
+    pub fn readable(&self) -> usize {
+        self.connection.readable()
+    }
+
+    /// Bytes that can be written without waiting.
+    pub fn writable(&self) -> usize {
+        self.connection.writable()
+    }
+}
+
+impl AsyncRead for LoopbackStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        // Safety: `Connection::poll_read` only writes initialized bytes into the
+        // slice and reports how many it wrote.
+        let unfilled = unsafe { buf.unfilled_mut() };
+        match self.connection.poll_read(cx, unfilled) {
+            Poll::Ready(n) => {
+                unsafe { buf.assume_init(n) };
+                buf.advance(n);
+                Poll::Ready(Ok(()))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncWrite for LoopbackStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.connection.poll_write(cx, data)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.connection.shutdown(Shutdown::Write);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    async fn guest_connect(
+        net: &LoopbackNetworking,
+        peer: SocketAddr,
+    ) -> Box<dyn VirtualTcpSocket + Sync> {
+        net.connect_tcp(addr("0.0.0.0:0"), peer).await.unwrap()
+    }
+
+    fn guest_recv(socket: &mut Box<dyn VirtualTcpSocket + Sync>) -> virtual_net::Result<Vec<u8>> {
+        let mut buf = [MaybeUninit::uninit(); 64];
+        let n = socket.try_recv(&mut buf)?;
+        // Safety: `try_recv` initialized the first `n` bytes.
+        Ok(buf[..n]
+            .iter()
+            .map(|byte| unsafe { byte.assume_init() })
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn guests_and_the_host_exchange_data() {
+        let net = LoopbackNetworking::new();
+        let listener = net.listen(addr("127.0.0.1:8080")).unwrap();
+        let mut guest = guest_connect(&net, addr("127.0.0.1:8080")).await;
+        let (mut host, peer) = listener.accept().await;
+        assert_eq!(peer, guest.addr_local().unwrap());
+        assert_eq!(guest.addr_peer().unwrap(), addr("127.0.0.1:8080"));
+
+        assert_eq!(guest.try_send(b"ping").unwrap(), 4);
+        let mut buf = [0; 4];
+        host.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        host.write_all(b"pong").await.unwrap();
+        assert_eq!(guest_recv(&mut guest).unwrap(), b"pong");
+        assert!(matches!(
+            guest_recv(&mut guest),
+            Err(NetworkError::WouldBlock)
+        ));
+    }
+
+    #[tokio::test]
+    async fn closing_one_end_is_end_of_file_for_the_other() {
+        let net = LoopbackNetworking::new();
+        let listener = net.listen(addr("127.0.0.1:9000")).unwrap();
+        let mut guest = guest_connect(&net, listener.local_addr()).await;
+        let (mut host, _) = listener.accept().await;
+
+        host.write_all(b"bye").await.unwrap();
+        host.shutdown().await.unwrap();
+        assert_eq!(guest_recv(&mut guest).unwrap(), b"bye");
+        assert_eq!(guest_recv(&mut guest).unwrap(), b"");
+
+        guest.try_send(b"late").unwrap();
+        drop(guest);
+        let mut rest = Vec::new();
+        host.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b"late");
+        assert!(host.write_all(b"x").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn writers_wait_for_a_full_buffer_to_drain() {
+        let net = LoopbackNetworking::new();
+        let listener = net.listen(addr("127.0.0.1:9001")).unwrap();
+        let mut guest = guest_connect(&net, listener.local_addr()).await;
+        let (mut host, _) = listener.accept().await;
+
+        host.write_all(&vec![7; BUFFER_SIZE]).await.unwrap();
+        assert_eq!(host.writable(), 0);
+        let blocked = tokio::time::timeout(Duration::from_millis(20), host.write(b"x")).await;
+        assert!(blocked.is_err(), "the write should wait for the reader");
+
+        assert_eq!(guest_recv(&mut guest).unwrap().len(), 64);
+        assert_eq!(host.writable(), 64);
+        assert_eq!(host.write(b"x").await.unwrap(), 1);
+        assert!(matches!(
+            guest.try_send(&vec![0; BUFFER_SIZE + 1]),
+            Ok(BUFFER_SIZE)
+        ));
+        assert!(matches!(
+            guest.try_send(b"y"),
+            Err(NetworkError::WouldBlock)
+        ));
+    }
+
+    #[tokio::test]
+    async fn port_zero_skips_ports_in_use() {
+        let net = LoopbackNetworking::new();
+        let taken = net
+            .listen(SocketAddr::new(
+                Ipv4Addr::LOCALHOST.into(),
+                FIRST_EPHEMERAL_PORT,
+            ))
+            .unwrap();
+        let listener = net.listen_tcp(addr("0.0.0.0:0"), false, false, false).await;
+        let port = listener.unwrap().addr_local().unwrap().port();
+        assert!(port >= FIRST_EPHEMERAL_PORT);
+        assert_ne!(port, taken.local_addr().port());
+
+        assert!(matches!(
+            net.listen(taken.local_addr()),
+            Err(NetworkError::AddressInUse)
+        ));
+        drop(taken);
+        net.listen(SocketAddr::new(
+            Ipv4Addr::LOCALHOST.into(),
+            FIRST_EPHEMERAL_PORT,
+        ))
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_loopback_is_reachable() {
+        let net = LoopbackNetworking::new();
+        assert!(matches!(
+            net.listen(addr("10.0.0.1:80")),
+            Err(NetworkError::PermissionDenied)
+        ));
+        assert!(matches!(
+            net.connect(addr("127.0.0.1:81")),
+            Err(NetworkError::ConnectionRefused)
+        ));
+        assert!(net.resolve("example.com", None, None).await.is_err());
+        assert_eq!(net.ip_list().unwrap().len(), 2);
+    }
+}
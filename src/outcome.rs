@@ -0,0 +1,145 @@
+use std::{fmt, process::ExitCode, time::Duration};
+
+use wasmer::{Pages, RuntimeError, Value};
+use wasmer_types::TrapCode;
+use wasmer_wasix::WasiError;
+
+/// Process exit code used when the guest traps.
+pub const TRAP_EXIT_CODE: i32 = 134;
+
+/// Process exit code used when the host fails while running the guest.
+pub const HOST_ERROR_EXIT_CODE: i32 = 1;
+
+/// Process exit code used when the guest runs out of fuel, as for SIGXCPU.
+pub const OUT_OF_FUEL_EXIT_CODE: i32 = 152;
+
+/// Process exit code used when the guest fails after filling its memory limit, as
+/// for an OOM kill.
+pub const MEMORY_LIMIT_EXIT_CODE: i32 = 137;
+
+/// Process exit code used when the guest overruns its deadline, as for timeout(1).
+pub const TIMED_OUT_EXIT_CODE: i32 = 124;
+
+/// How a call into the guest ended.
+#[derive(Debug)]
+pub enum Outcome {
+    /// The guest returned normally or called `proc_exit`.
+    Exited(i32),
+    /// The guest hit a WebAssembly trap.
+    Trapped { code: TrapCode, error: RuntimeError },
+    /// Something on the host side failed, e.g. a host function returned an error.
+    HostError(RuntimeError),
+    /// The guest used up its fuel.
+    OutOfFuel,
+    /// The guest failed after the page limit turned down a grow of one of its memories.
+    MemoryLimit { memory: String, limit: Pages },
+    /// The guest was still running when its deadline passed.
+    TimedOut(Duration),
+}
+
+impl Outcome {
+    pub fn from_call(result: Result<Box<[Value]>, RuntimeError>) -> Self {
+        let err = match result {
+            Ok(_) => return Outcome::Exited(0),
+            Err(err) => err,
+        };
+
+        let err = match err.downcast::<WasiError>() {
+            Ok(WasiError::Exit(code)) => return Outcome::Exited(code.raw()),
+            Ok(other) => return Outcome::HostError(RuntimeError::user(Box::new(other))),
+            Err(err) => err,
+        };
+
+        match err.clone().to_trap() {
+            Some(code) => Outcome::Trapped { code, error: err },
+            None => Outcome::HostError(err),
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Outcome::Exited(code) => *code,
+            Outcome::Trapped { .. } => TRAP_EXIT_CODE,
+            Outcome::HostError(_) => HOST_ERROR_EXIT_CODE,
+            Outcome::OutOfFuel => OUT_OF_FUEL_EXIT_CODE,
+            Outcome::MemoryLimit { .. } => MEMORY_LIMIT_EXIT_CODE,
+            Outcome::TimedOut(_) => TIMED_OUT_EXIT_CODE,
+        }
+    }
+
+    /// The exit code truncated to the byte a process can return, keeping failures
+    /// whose low byte is zero, e.g. `proc_exit(256)`, from looking like success.
+    pub fn process_exit_code(&self) -> ExitCode {
+        match self.exit_code() {
+            code if code != 0 && code as u8 == 0 => ExitCode::FAILURE,
+            code => ExitCode::from(code as u8),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Exited(0) => write!(f, "Success"),
+            Outcome::Exited(code) => write!(f, "Guest exited with code {code}"),
+            Outcome::Trapped { code, error } => write!(f, "Guest trapped ({code}): {error}"),
+            Outcome::HostError(error) => write!(f, "Host error: {error}"),
+            Outcome::OutOfFuel => write!(f, "Guest ran out of fuel"),
+            Outcome::MemoryLimit { memory, limit } => write!(
+                f,
+                "Guest ran out of memory: `{memory}` can't grow past the limit of {} pages",
+                limit.0
+            ),
+            Outcome::TimedOut(timeout) => write!(f, "Guest timed out after {timeout:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmer::{imports, Instance, Module, Store};
+
+    use super::*;
+
+    fn call_export(wat: &str) -> Result<Box<[Value]>, RuntimeError> {
+        let mut store = Store::default();
+        let module = Module::new(&store, wat).unwrap();
+        let instance = Instance::new(&mut store, &module, &imports! {}).unwrap();
+        let function = instance.exports.get_function("run").unwrap();
+        function.call(&mut store, &[])
+    }
+
+    #[test]
+    fn classifies_traps_by_code() {
+        let result = call_export(r#"(module (func (export "run") unreachable))"#);
+        match Outcome::from_call(result) {
+            Outcome::Trapped { code, .. } => assert_eq!(code, TrapCode::UnreachableCodeReached),
+            other => panic!("expected a trap, got {other}"),
+        }
+    }
+
+    #[test]
+    fn classifies_exits_and_host_errors() {
+        assert!(matches!(
+            Outcome::from_call(Ok(Box::new([]))),
+            Outcome::Exited(0)
+        ));
+
+        let exit = RuntimeError::user(Box::new(WasiError::Exit(3.into())));
+        assert!(matches!(Outcome::from_call(Err(exit)), Outcome::Exited(3)));
+
+        let failed = RuntimeError::new("the host gave up");
+        assert!(matches!(
+            Outcome::from_call(Err(failed)),
+            Outcome::HostError(_)
+        ));
+    }
+
+    #[test]
+    fn keeps_failures_nonzero_in_the_process_exit_code() {
+        assert_eq!(Outcome::Exited(0).process_exit_code(), ExitCode::SUCCESS);
+        assert_eq!(Outcome::Exited(3).process_exit_code(), ExitCode::from(3));
+        assert_eq!(Outcome::Exited(256).process_exit_code(), ExitCode::FAILURE);
+        assert_eq!(Outcome::OutOfFuel.process_exit_code(), ExitCode::from(152));
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use wasmer_wasix::{
+    capabilities::{Capabilities, CapabilityThreadingV1},
+    http::HttpClientCapabilityV1,
+};
+
+use crate::{
+    error::{Error, Result},
+    mounts::{Mount, MountSource},
+};
+
+/// What a guest is allowed to do, loaded from a TOML or JSON file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    pub http: HttpPolicy,
+    pub threading: ThreadingPolicy,
+    pub mounts: Vec<MountPolicy>,
+    pub env: BTreeMap<String, String>,
+    /// Directory of the policy file, relative host paths are resolved against it.
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpPolicy {
+    /// Hosts the guest may send requests to, `*` allows any host.
+    pub allowed_hosts: Vec<String>,
+    /// Methods the guest may use, empty allows any method. Enforced by the `--http-*` backends.
+    pub allowed_methods: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThreadingPolicy {
+    pub max_threads: Option<usize>,
+    pub asynchronous: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MountPolicy {
+    pub guest: PathBuf,
+    /// Host directory to mount, an in-memory directory is mounted when unset.
+    pub host: Option<PathBuf>,
+    /// Directory or tarball an in-memory mount starts out with.
+    pub seed: Option<PathBuf>,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).map_err(Error::file("read policy", path))?;
+        let mut policy: Policy = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&text).map_err(Error::config("policy", path))?
+        } else {
+            toml::from_str(&text).map_err(Error::config("policy", path))?
+        };
+        policy.dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(policy)
+    }
+
+    /// Looks for `<module>.policy.toml` or `<module>.policy.json` next to the module.
+    pub fn discover(module: &Path) -> Option<PathBuf> {
+        ["toml", "json"]
+            .into_iter()
+            .map(|ext| module.with_extension(format!("policy.{ext}")))
+            .find(|path| path.is_file())
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        let allow_all = self.http.allowed_hosts.iter().any(|host| host == "*");
+        let allowed_hosts = match allow_all {
+            true => HashSet::new(),
+            false => self.http.allowed_hosts.iter().cloned().collect(),
+        };
+
+        Capabilities {
+            insecure_allow_all: false,
+            http_client: HttpClientCapabilityV1 {
+                allow_all,
+                allowed_hosts,
+            },
+            threading: CapabilityThreadingV1 {
+                max_threads: self.threading.max_threads,
+                enable_asynchronous_threading: self.threading.asynchronous,
+            },
+        }
+    }
+
+    pub fn mounts(&self) -> Vec<Mount> {
+        self.mounts
+            .iter()
+            .map(|mount| Mount {
+                guest: mount.guest.clone(),
+                source: match &mount.host {
+                    Some(host) => MountSource::Host(self.dir.join(host)),
+                    None => MountSource::Memory {
+                        seed: mount.seed.as_ref().map(|seed| self.dir.join(seed)),
+                    },
+                },
+                read_only: mount.read_only,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |items: &[String]| {
+            if items.is_empty() {
+                "none".to_string()
+            } else {
+                items.join(", ")
+            }
+        };
+        write!(
+            f,
+            "http hosts: {}; http methods: {}; threads: {}; mounts: {}; env vars: {}",
+            list(&self.http.allowed_hosts),
+            if self.http.allowed_methods.is_empty() {
+                "any".to_string()
+            } else {
+                self.http.allowed_methods.join(", ")
+            },
+            self.threading
+                .max_threads
+                .map_or("unlimited".to_string(), |max| max.to_string()),
+            self.mounts.len(),
+            self.env.len(),
+        )
+    }
+}
+
+/// Capabilities for running without a policy, everything is allowed.
+pub fn insecure_capabilities() -> Capabilities {
+    Capabilities {
+        insecure_allow_all: true,
+        http_client: HttpClientCapabilityV1::new_allow_all(),
+        threading: CapabilityThreadingV1::default(),
+    }
+}
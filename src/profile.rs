@@ -0,0 +1,166 @@
+use std::fmt;
+
+use serde::Serialize;
+use wasmparser::{Parser, Payload};
+
+use crate::error::{Error, Result};
+
+/// Strings only Mono's runtime carries, e.g. in its GC option parser.
+const MONO_MARKERS: &[&[u8]] = &[b"MONO_GC_PARAMS", b"mono_runtime_"];
+
+/// Symbols of the NativeAOT runtime and its mangled CoreLib.
+const NATIVE_AOT_MARKERS: &[&[u8]] = &[b"RhpNewFast", b"S_P_CoreLib_"];
+
+/// The .NET runtime a module was built with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum DotnetRuntime {
+    /// Mono, as in the `wasi-experimental` workload
+    Mono,
+    /// NativeAOT-LLVM
+    NativeAot,
+}
+
+impl DotnetRuntime {
+    /// Guesses the runtime from strings in the module's data and name sections.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        let contains = |needle: &[u8]| bytes.windows(needle.len()).any(|window| window == needle);
+        if MONO_MARKERS.iter().any(|marker| contains(marker)) {
+            Some(DotnetRuntime::Mono)
+        } else if NATIVE_AOT_MARKERS.iter().any(|marker| contains(marker)) {
+            Some(DotnetRuntime::NativeAot)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DotnetRuntime::Mono => "Mono",
+            DotnetRuntime::NativeAot => "NativeAOT",
+        }
+    }
+}
+
+impl fmt::Display for DotnetRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Which profile to apply to a guest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ProfileChoice {
+    /// Detect the runtime from the module
+    #[default]
+    Auto,
+    Mono,
+    NativeAot,
+    /// Don't apply any runtime-specific settings
+    None,
+}
+
+impl ProfileChoice {
+    pub fn resolve(self, bytes: &[u8]) -> RuntimeProfile {
+        match self {
+            ProfileChoice::Auto => RuntimeProfile::detect(bytes),
+            ProfileChoice::Mono => RuntimeProfile::for_runtime(Some(DotnetRuntime::Mono)),
+            ProfileChoice::NativeAot => RuntimeProfile::for_runtime(Some(DotnetRuntime::NativeAot)),
+            ProfileChoice::None => RuntimeProfile::default(),
+        }
+    }
+}
+
+/// Host settings that make a runtime's guests work without hand-tuning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeProfile {
+    pub runtime: Option<DotnetRuntime>,
+    /// Environment variables set unless the caller sets them too.
+    pub env: Vec<(&'static str, &'static str)>,
+    /// Memory limit, in pages, used when none is configured.
+    pub memory_pages: Option<u32>,
+    /// Export that runs `Main` in a command module.
+    pub entry: &'static str,
+    /// Exports that set up a library module, tried in order.
+    pub initializers: &'static [&'static str],
+}
+
+impl RuntimeProfile {
+    pub fn for_runtime(runtime: Option<DotnetRuntime>) -> Self {
+        match runtime {
+            // Mono sizes its heap from the GC options rather than from the memory it
+            // can grow into, keep the two consistent so it collects before it traps.
+            Some(DotnetRuntime::Mono) => RuntimeProfile {
+                runtime,
+                env: vec![("MONO_GC_PARAMS", "max-heap-size=384m,nursery-size=4m")],
+                memory_pages: Some(8192),
+                entry: "_start",
+                initializers: &["_initialize"],
+            },
+            // Older NativeAOT-LLVM library builds export the runtime's startup
+            // instead of wasi-libc's reactor entry point.
+            Some(DotnetRuntime::NativeAot) => RuntimeProfile {
+                runtime,
+                env: Vec::new(),
+                memory_pages: None,
+                entry: "_start",
+                initializers: &["_initialize", "__managed__Startup"],
+            },
+            None => RuntimeProfile::default(),
+        }
+    }
+
+    pub fn detect(bytes: &[u8]) -> Self {
+        Self::for_runtime(DotnetRuntime::detect(bytes))
+    }
+
+    /// Rejects builds for another host, e.g. Mono's browser (Emscripten) flavour,
+    /// which imports its runtime support from `env` instead of WASI.
+    pub fn check_imports(&self, bytes: &[u8]) -> Result<()> {
+        if self.runtime != Some(DotnetRuntime::Mono) {
+            return Ok(());
+        }
+        for payload in Parser::new(0).parse_all(bytes) {
+            if let Payload::ImportSection(reader) = payload? {
+                for import in reader {
+                    if import?.module == "env" {
+                        return Err(Error::InvalidArgument(
+                            "this is a Mono browser build that imports from `env`, rebuild it \
+                             for WASI with the wasi-experimental workload"
+                                .to_string(),
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for RuntimeProfile {
+    fn default() -> Self {
+        RuntimeProfile {
+            runtime: None,
+            env: Vec::new(),
+            memory_pages: None,
+            entry: "_start",
+            initializers: &["_initialize"],
+        }
+    }
+}
+
+impl fmt::Display for RuntimeProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.runtime {
+            Some(runtime) => write!(f, "{runtime} guest")?,
+            None => write!(f, "unrecognized guest")?,
+        }
+        for (key, value) in &self.env {
+            write!(f, ", {key}={value}")?;
+        }
+        if let Some(pages) = self.memory_pages {
+            write!(f, ", memory limit {pages} pages")?;
+        }
+        Ok(())
+    }
+}
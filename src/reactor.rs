@@ -0,0 +1,50 @@
+use wasmer::{FunctionType, Value};
+
+use crate::{
+    error::{Error, Result},
+    runtime::GuestInstance,
+};
+
+/// A guest that stays alive between export calls, like a plugin.
+///
+/// The module is initialized once when the reactor is created and `cleanup` only runs
+/// on [`Reactor::shutdown`], so state the guest keeps in memory survives across calls.
+pub struct Reactor {
+    instance: GuestInstance,
+}
+
+impl Reactor {
+    pub fn new(mut instance: GuestInstance) -> Result<Self> {
+        let initialized = instance.initialize()?.is_some();
+        let command = instance
+            .exports()
+            .get_function(instance.profile().entry)
+            .is_ok();
+        // A module with neither has nothing to set up, e.g. one without a libc.
+        if !initialized && command {
+            return Err(Error::NotAReactor);
+        }
+        Ok(Self { instance })
+    }
+
+    pub fn functions(&self) -> Vec<(String, FunctionType)> {
+        self.instance.functions()
+    }
+
+    pub fn module_bytes(&self) -> &[u8] {
+        self.instance.module_bytes()
+    }
+
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Box<[Value]>> {
+        self.instance.call(name, args)
+    }
+
+    pub fn invoke(&mut self, name: &str, args: &[String]) -> Result<Box<[Value]>> {
+        self.instance.invoke(name, args)
+    }
+
+    /// Runs the guest's cleanup and flushes its output.
+    pub fn shutdown(self) -> Result<()> {
+        self.instance.finish()
+    }
+}
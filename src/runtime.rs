@@ -0,0 +1,916 @@
+use std::{
+    borrow::Cow,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{runtime::Handle, task::JoinHandle};
+use virtual_fs::Pipe;
+use wasmer::{
+    CompileError, Engine, Exports, Features, Function, FunctionType, Instance, Module,
+    NativeEngineExt, Pages, RuntimeError, Store, Value,
+};
+use wasmer_compiler::ModuleEnvironment;
+use wasmer_wasix::{
+    capabilities::Capabilities, PluggableRuntime, WasiEnv, WasiEnvBuilder, WasiFunctionEnv,
+};
+
+use crate::{
+    artifact::{Artifact, ArtifactHeader},
+    cache::{CacheStatus, ModuleCache},
+    compiler::Backend,
+    error::{Error, Result},
+    features::{self, FeatureOverrides},
+    http::{HttpBackend, HttpMode},
+    imports::HostImports,
+    inspect::ModuleReport,
+    limits::{deadline, metering, remaining_fuel, set_fuel, Deadline, DeadlineExceeded},
+    mounts::{mount_all, Mount},
+    net::LoopbackNetworking,
+    outcome::Outcome,
+    policy::{HttpPolicy, Policy},
+    profile::{ProfileChoice, RuntimeProfile},
+    reactor::Reactor,
+    stdin::{spawn_stdin, StdinSource},
+    stdio::{GuestStdout, StdioForwarder, StreamOptions},
+    symbols::{Symbolizer, TrapReport},
+    tunables::{memory_usage, LimitingTunables, RejectedGrows, ResourceLimits},
+    values::{format_signature, parse_args},
+    wai::{self, WaiImports},
+};
+
+pub const DEFAULT_PROGRAM_NAME: &str = "nor2";
+
+/// The guest built into the host. With the `embedded-artifact` feature it is the
+/// artifact at the absolute path in `CS_GUEST_ARTIFACT` at build time, made with
+/// the `compile` command, so the bundled guest starts without compiling.
+#[cfg(not(feature = "embedded-artifact"))]
+const BUNDLED: &[u8] = include_bytes!("../cswasi.wasm");
+#[cfg(feature = "embedded-artifact")]
+const BUNDLED: &[u8] = include_bytes!(env!("CS_GUEST_ARTIFACT"));
+
+/// Where the guest module comes from.
+#[derive(Debug, Clone, Default)]
+pub enum ModuleSource {
+    /// The `cswasi.wasm` sample built into the host, possibly precompiled.
+    #[default]
+    Bundled,
+    File(PathBuf),
+    Bytes(Vec<u8>),
+    /// An artifact made by [`CsGuestRuntime::precompile`]. Its native code runs
+    /// without being checked, so only load artifacts you built or trust.
+    Artifact(PathBuf),
+}
+
+impl ModuleSource {
+    pub fn load(&self) -> Result<Cow<'static, [u8]>> {
+        match self {
+            ModuleSource::Bundled => Ok(Cow::Borrowed(BUNDLED)),
+            ModuleSource::File(path) | ModuleSource::Artifact(path) => {
+                let bytes = std::fs::read(path).map_err(Error::file("read guest module", path))?;
+                Ok(Cow::Owned(bytes))
+            }
+            ModuleSource::Bytes(bytes) => Ok(Cow::Owned(bytes.clone())),
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            ModuleSource::File(path) | ModuleSource::Artifact(path) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    pub dir: PathBuf,
+    pub max_size: u64,
+}
+
+/// Builder for a sandboxed C# guest.
+///
+/// Without a policy or explicit capabilities the guest gets no HTTP access, mounts or
+/// extra environment variables.
+pub struct CsGuestRuntime {
+    module: ModuleSource,
+    backend: Backend,
+    fallback: bool,
+    features: Option<Features>,
+    feature_overrides: FeatureOverrides,
+    cache: Option<CacheConfig>,
+    capabilities: Capabilities,
+    http_policy: Option<HttpPolicy>,
+    http: Option<HttpMode>,
+    network: Option<LoopbackNetworking>,
+    imports: HostImports,
+    stub_imports: bool,
+    wai: WaiImports,
+    guest_stdout: GuestStdout,
+    program_name: String,
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    cwd: Option<PathBuf>,
+    mounts: Vec<Mount>,
+    overlay: bool,
+    stdin: StdinSource,
+    stdout: StreamOptions,
+    stderr: StreamOptions,
+    limits: ResourceLimits,
+    fuel: Option<u64>,
+    timeout: Option<Duration>,
+    profile: ProfileChoice,
+    rejected_grows: RejectedGrows,
+}
+
+impl Default for CsGuestRuntime {
+    fn default() -> Self {
+        let guest_stdout = GuestStdout::new();
+        Self {
+            module: ModuleSource::default(),
+            backend: Backend::default(),
+            fallback: true,
+            features: None,
+            feature_overrides: FeatureOverrides::default(),
+            cache: None,
+            capabilities: Policy::default().capabilities(),
+            http_policy: None,
+            http: None,
+            network: None,
+            imports: HostImports::new(),
+            stub_imports: false,
+            wai: WaiImports::new(),
+            guest_stdout,
+            program_name: DEFAULT_PROGRAM_NAME.to_string(),
+            args: Vec::new(),
+            envs: Vec::new(),
+            cwd: None,
+            mounts: Vec::new(),
+            overlay: false,
+            stdin: StdinSource::default(),
+            stdout: StreamOptions::default(),
+            stderr: StreamOptions::default(),
+            limits: ResourceLimits::default(),
+            fuel: None,
+            timeout: None,
+            profile: ProfileChoice::default(),
+            rejected_grows: RejectedGrows::default(),
+        }
+    }
+}
+
+impl CsGuestRuntime {
+    pub fn new(module: ModuleSource) -> Self {
+        Self {
+            module,
+            ..Self::default()
+        }
+    }
+
+    pub fn module_source(&self) -> &ModuleSource {
+        &self.module
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Whether to retry with Cranelift when the chosen backend can't compile the module.
+    pub fn fallback(mut self, fallback: bool) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Enables exactly these proposals instead of the ones detected in the module.
+    pub fn features(mut self, features: Features) -> Self {
+        self.features = Some(features);
+        self
+    }
+
+    /// Forces proposals on or off on top of the detected or explicit features.
+    pub fn feature_overrides(mut self, overrides: FeatureOverrides) -> Self {
+        self.feature_overrides = overrides;
+        self
+    }
+
+    /// Keeps compiled modules in an on-disk cache, the module is compiled every time
+    /// otherwise.
+    pub fn cache(mut self, cache: CacheConfig) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Applies a policy's capabilities, environment and mounts.
+    pub fn policy(mut self, policy: &Policy) -> Self {
+        self.capabilities = policy.capabilities();
+        self.http_policy = Some(policy.http.clone());
+        self.envs.extend(policy.env.clone());
+        self.mounts.extend(policy.mounts());
+        self
+    }
+
+    /// Where guest HTTP requests are answered from, the guest has no HTTP client otherwise.
+    pub fn http(mut self, mode: HttpMode) -> Self {
+        self.http = Some(mode);
+        self
+    }
+
+    /// Gives the guest a loopback-only network, shared with every other guest and host
+    /// handle using the same `network`.
+    pub fn network(mut self, network: LoopbackNetworking) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Untyped host functions, for imports no WAI interface covers.
+    pub fn imports(mut self, imports: HostImports) -> Self {
+        self.imports = imports;
+        self
+    }
+
+    /// Fills imports nothing provides with functions that trap when called, so the
+    /// guest can run as long as it stays off the paths that need them.
+    pub fn stub_missing_imports(mut self, stub: bool) -> Self {
+        self.stub_imports = stub;
+        self
+    }
+
+    /// Host interfaces generated from `.wai` files. There are none by default.
+    pub fn wai(mut self, wai: WaiImports) -> Self {
+        self.wai = wai;
+        self
+    }
+
+    /// Links the interfaces the bundled samples import, see
+    /// [`WaiImports::with_samples`].
+    pub fn sample_imports(mut self) -> Self {
+        self.wai = WaiImports::with_samples(self.guest_stdout.clone());
+        self
+    }
+
+    /// Where host functions should print, so their output stays in order with
+    /// the guest's.
+    pub fn guest_stdout(&self) -> GuestStdout {
+        self.guest_stdout.clone()
+    }
+
+    pub fn program_name(mut self, name: impl Into<String>) -> Self {
+        self.program_name = name.into();
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn envs<I>(mut self, envs: I) -> Self
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        self.envs.extend(envs);
+        self
+    }
+
+    /// The guest's working directory, an absolute path in its filesystem. Relative
+    /// paths resolve against it, and it is created empty unless a mount covers it.
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(dir.into());
+        self
+    }
+
+    pub fn mounts<I>(mut self, mounts: I) -> Self
+    where
+        I: IntoIterator<Item = Mount>,
+    {
+        self.mounts.extend(mounts);
+        self
+    }
+
+    /// Mounts host directories as in-memory snapshots, so guest writes never reach
+    /// the host. Directories over [`OVERLAY_MAX_SIZE`](crate::mounts::OVERLAY_MAX_SIZE)
+    /// are refused.
+    pub fn overlay(mut self, overlay: bool) -> Self {
+        self.overlay = overlay;
+        self
+    }
+
+    pub fn stdin(mut self, source: StdinSource) -> Self {
+        self.stdin = source;
+        self
+    }
+
+    pub fn stdout(mut self, options: StreamOptions) -> Self {
+        self.stdout = options;
+        self
+    }
+
+    pub fn stderr(mut self, options: StreamOptions) -> Self {
+        self.stderr = options;
+        self
+    }
+
+    pub fn limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn fuel(mut self, fuel: Option<u64>) -> Self {
+        self.fuel = fuel;
+        self
+    }
+
+    /// Stops the guest once `_start`, or any single export call, has run this long.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Runtime-specific defaults to apply, detected from the module by default.
+    pub fn profile(mut self, profile: ProfileChoice) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Fills in the profile's settings wherever the caller left the default.
+    fn apply_profile(&mut self, profile: &RuntimeProfile, bytes: &[u8]) -> Result<()> {
+        profile.check_imports(bytes)?;
+
+        let defaults: Vec<(String, String)> = profile
+            .env
+            .iter()
+            .filter(|(key, _)| !self.envs.iter().any(|(set, _)| set == key))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        self.envs.splice(0..0, defaults);
+
+        if self.limits.memory_pages.is_none() {
+            self.limits.memory_pages = profile.memory_pages;
+        }
+        Ok(())
+    }
+
+    fn create_wasi_env(&self, handle: &Handle) -> Result<(WasiEnvBuilder, Pipe, Pipe, Pipe)> {
+        let (stdin_tx, stdin_rx) = Pipe::channel();
+        let (stdout, stdout_rx) = Pipe::channel();
+        let (stderr, stderr_rx) = Pipe::channel();
+        self.guest_stdout.connect(stdout.clone());
+
+        let mut runtime = PluggableRuntime::new(Arc::new(
+            wasmer_wasix::runtime::task_manager::tokio::TokioTaskManager::new(handle.clone()),
+        ));
+        if let Some(network) = &self.network {
+            runtime.set_networking_implementation(network.clone());
+        }
+        if let Some(mode) = &self.http {
+            let backend = HttpBackend::open(mode.clone(), self.http_policy.as_ref())?;
+            runtime.http_client = Some(Arc::new(backend));
+        }
+
+        let mut builder = WasiEnv::builder(self.program_name.as_str())
+            .runtime(Arc::new(runtime))
+            .capabilities(self.capabilities.clone())
+            .stdin(Box::new(stdin_rx))
+            .stdout(Box::new(stdout))
+            .stderr(Box::new(stderr))
+            .env("RUST_LOG", "trace")
+            .env("WASMER_BACKTRACE", "1")
+            .env("RUST_BACKTRACE", "wasmer_wasix=trace")
+            .envs(self.envs.iter().cloned())
+            .args(&self.args);
+
+        // wasix keeps its own working directory private, so the guest finds it
+        // through the `.` preopen and `PWD` instead.
+        if let Some(cwd) = &self.cwd {
+            if !self.envs.iter().any(|(key, _)| key == "PWD") {
+                builder = builder.env("PWD", cwd.to_string_lossy().as_ref());
+            }
+        }
+
+        let builder = mount_all(builder, &self.mounts, self.overlay, self.cwd.as_deref())?;
+
+        Ok((builder, stdin_tx, stdout_rx, stderr_rx))
+    }
+
+    fn compile_module(&self, store: &Store, features: &Features, bytes: &[u8]) -> Result<Module> {
+        let Some(config) = &self.cache else {
+            return Ok(Module::new(store, bytes)?);
+        };
+
+        let mut cache = ModuleCache::open(config.dir.clone(), config.max_size)?;
+        let (module, status) = cache.load_or_compile(store, features, &self.variant(), bytes)?;
+        match status {
+            CacheStatus::Hit => log::info!("Module cache hit ({})", cache.dir().display()),
+            CacheStatus::Miss => log::info!("Module cache miss, compiled and stored the module"),
+        }
+        Ok(module)
+    }
+
+    /// Anything besides the engine and features that changes the generated code.
+    fn variant(&self) -> String {
+        let mut variant = self.limits.cache_tag();
+        if self.fuel.is_some() {
+            variant.push_str("metering");
+        }
+        if self.timeout.is_some() {
+            variant.push_str("deadline");
+        }
+        variant
+    }
+
+    /// The guest's WebAssembly, and its precompiled code when the source is an
+    /// artifact. Only sources that say so are read as artifacts.
+    fn load(&self) -> Result<(Cow<'static, [u8]>, Option<Artifact>)> {
+        let bytes = self.module.load()?;
+        let precompiled = match self.module {
+            ModuleSource::Artifact(_) => true,
+            ModuleSource::Bundled => cfg!(feature = "embedded-artifact"),
+            ModuleSource::File(_) | ModuleSource::Bytes(_) => false,
+        };
+        if !precompiled {
+            return Ok((bytes, None));
+        }
+        let mut artifact = Artifact::parse(&bytes)?;
+        let wasm = std::mem::take(&mut artifact.wasm);
+        Ok((Cow::Owned(wasm), Some(artifact)))
+    }
+
+    /// The proposals to enable: the explicit ones or those the module needs, with
+    /// the overrides applied.
+    fn module_features(&self, bytes: &[u8]) -> Result<Features> {
+        let mut features = match &self.features {
+            Some(features) => features.clone(),
+            None => features::detect(bytes)?,
+        };
+        self.feature_overrides.apply(&mut features)?;
+        Ok(features)
+    }
+
+    fn engine(&self, backend: Backend, features: &Features) -> Result<Engine> {
+        let unsupported = backend.unsupported_features(features);
+        if !unsupported.is_empty() {
+            return Err(Error::UnsupportedFeatures {
+                backend,
+                features: unsupported,
+            });
+        }
+
+        let mut middlewares = Vec::new();
+        if self.fuel.is_some() {
+            middlewares.push(metering());
+        }
+        if self.timeout.is_some() {
+            middlewares.push(deadline());
+        }
+        let mut engine = backend.engine(features, &middlewares);
+        if !self.limits.is_unlimited() {
+            engine.set_tunables(LimitingTunables::for_host(
+                self.limits,
+                self.rejected_grows.clone(),
+            ));
+        }
+        Ok(engine)
+    }
+
+    fn compile_with_backend(
+        &self,
+        backend: Backend,
+        features: &Features,
+        bytes: &[u8],
+    ) -> Result<(Store, Module)> {
+        let store = Store::new(self.engine(backend, features)?);
+        let module = self.compile_module(&store, features, bytes)?;
+        Ok((store, module))
+    }
+
+    /// Loads an artifact's code with the compiler that generated it, after checking
+    /// the rest of the header matches how this configuration would compile the module.
+    fn deserialize(&self, artifact: Artifact, features: &Features) -> Result<(Store, Module)> {
+        let compiler = &artifact.header.compiler;
+        let Some(backend) = Backend::from_name(compiler) else {
+            return Err(Error::ArtifactMismatch {
+                field: "compiler",
+                artifact: compiler.clone(),
+                host: Backend::ALL.map(Backend::name).join(" or "),
+            });
+        };
+        let engine = self.engine(backend, features)?;
+        let host = ArtifactHeader::new(&engine, backend, features, self.variant());
+        artifact.header.check(&host)?;
+
+        let store = Store::new(engine);
+        // Safety: the header matched this host's engine, compiler, features and
+        // target, so the code is what this store would have generated itself.
+        let module = unsafe { Module::deserialize(&store, artifact.compiled)? };
+        log::info!("Loaded precompiled module ({})", artifact.header);
+        Ok((store, module))
+    }
+
+    /// Compiles with the requested backend, retrying with Cranelift if that fails.
+    /// Precompiled code is used as is.
+    fn compile(&self, bytes: &[u8], artifact: Option<Artifact>) -> Result<(Store, Module)> {
+        let features = self.module_features(bytes)?;
+        if let Some(artifact) = artifact {
+            return self.deserialize(artifact, &features);
+        }
+        match self.compile_with_backend(self.backend, &features, bytes) {
+            Err(err) if self.backend != Backend::Cranelift && self.fallback => {
+                log::warn!(
+                    "{} could not compile the module: {err}\nFalling back to {}",
+                    self.backend,
+                    Backend::Cranelift
+                );
+                self.compile_with_backend(Backend::Cranelift, &features, bytes)
+            }
+            result => result,
+        }
+    }
+
+    /// Reports the module's contents, the proposals it would be compiled with and
+    /// the imports this host configuration leaves unsatisfied. The module is
+    /// validated against those proposals, but neither compiled nor run.
+    pub fn inspect(self) -> Result<ModuleReport> {
+        let (bytes, _) = self.load()?;
+        let features = self.module_features(&bytes)?;
+        let engine = match self.engine(self.backend, &features) {
+            Err(err) if self.backend != Backend::Cranelift && self.fallback => {
+                log::warn!("{err}\nFalling back to {}", Backend::Cranelift);
+                self.engine(Backend::Cranelift, &features)?
+            }
+            engine => engine?,
+        };
+        let mut store = Store::new(engine);
+        Module::validate(&store, &bytes)?;
+        let module = ModuleEnvironment::new()
+            .translate(&bytes)
+            .map_err(CompileError::Wasm)?
+            .module;
+
+        let mut report = ModuleReport::new(&module, &bytes, &features)?;
+        let wai = self.wai.link(&mut store, &module);
+        report.unsatisfied_imports = self
+            .imports
+            .check(&module, &wai.imports)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        Ok(report)
+    }
+
+    /// Compiles the module ahead of time with the configured backend, features and
+    /// limits, and writes an artifact [`CsGuestRuntime::instantiate`] loads
+    /// without compiling.
+    pub fn precompile(mut self, output: &Path) -> Result<ArtifactHeader> {
+        let (bytes, artifact) = self.load()?;
+        if artifact.is_some() {
+            return Err(Error::InvalidArgument(
+                "the module is already precompiled".to_string(),
+            ));
+        }
+        let profile = self.profile.resolve(&bytes);
+        self.apply_profile(&profile, &bytes)?;
+        self.limits.check_module(&bytes)?;
+
+        let features = self.module_features(&bytes)?;
+        let engine = self.engine(self.backend, &features)?;
+        let header = ArtifactHeader::new(&engine, self.backend, &features, self.variant());
+        let module = Module::new(&Store::new(engine), &bytes)?;
+
+        let artifact = Artifact {
+            header,
+            wasm: bytes.into_owned(),
+            compiled: module.serialize()?.to_vec(),
+        };
+        fs::write(output, artifact.to_bytes()).map_err(Error::file("write artifact", output))?;
+        Ok(artifact.header)
+    }
+
+    /// Instantiates the guest and initializes it once for repeated export calls.
+    pub fn reactor(self, handle: &Handle) -> Result<Reactor> {
+        Reactor::new(self.instantiate(handle)?)
+    }
+
+    /// Compiles and instantiates the guest, with its stdio already being forwarded.
+    pub fn instantiate(mut self, handle: &Handle) -> Result<GuestInstance> {
+        let (bytes, artifact) = self.load()?;
+        let profile = self.profile.resolve(&bytes);
+        self.apply_profile(&profile, &bytes)?;
+        log::info!("Guest profile: {profile}");
+
+        let (builder, stdin_tx, stdout_rx, stderr_rx) = self.create_wasi_env(handle)?;
+
+        let forwarder = StdioForwarder::spawn(
+            handle,
+            stdout_rx,
+            stderr_rx,
+            self.stdout.clone(),
+            self.stderr.clone(),
+        )?;
+
+        self.limits.check_module(&bytes)?;
+        let (mut store, module) = self.compile(&bytes, artifact)?;
+
+        let mut wasi_env = builder.finalize(&mut store)?;
+
+        let wai = std::mem::take(&mut self.wai).link(&mut store, module.info());
+        let mut import_object =
+            wasi_env.import_object_for_all_wasi_versions(&mut store, &module)?;
+        import_object.extend(&wai.imports);
+        let extend = if self.stub_imports {
+            self.imports
+                .resolve_with_stubs(&mut store, &module, &import_object)?
+        } else {
+            self.imports.resolve(&mut store, &module, &import_object)?
+        };
+        import_object.extend(extend);
+        let deadline = match self.timeout {
+            Some(_) => Some(Deadline::wrap(&mut store, &module, &mut import_object)?),
+            None => None,
+        };
+
+        let instance = Instance::new(&mut store, &module, &import_object)?;
+        if let Some(deadline) = &deadline {
+            deadline.attach(&mut store, &instance)?;
+        }
+        wai.initialize(&mut store, &instance, &bytes)?;
+
+        wasi_env.data(&store).thread.set_status_running();
+
+        wasi_env
+            .initialize(&mut store, instance.clone())
+            .map_err(Error::WasiExport)?;
+
+        let stdin_task = spawn_stdin(handle, self.stdin.clone(), stdin_tx);
+
+        if let Some(fuel) = self.fuel {
+            set_fuel(&mut store, &instance, fuel);
+        }
+
+        Ok(GuestInstance {
+            store,
+            instance,
+            wasi_env,
+            bytes,
+            profile,
+            forwarder,
+            stdin_task,
+            fuel: self.fuel,
+            timeout: self.timeout,
+            deadline,
+            rejected_grows: self.rejected_grows,
+        })
+    }
+}
+
+/// An instantiated guest, ready to run `_start` or have its exports called.
+pub struct GuestInstance {
+    store: Store,
+    instance: Instance,
+    wasi_env: WasiFunctionEnv,
+    bytes: Cow<'static, [u8]>,
+    profile: RuntimeProfile,
+    forwarder: StdioForwarder,
+    stdin_task: JoinHandle<()>,
+    fuel: Option<u64>,
+    timeout: Option<Duration>,
+    deadline: Option<Deadline>,
+    rejected_grows: RejectedGrows,
+}
+
+/// What happened during [`GuestInstance::run`].
+#[derive(Debug)]
+pub struct RunReport {
+    pub outcome: Outcome,
+    /// Fuel left when the guest finished, `None` without a fuel limit.
+    pub remaining_fuel: Option<u64>,
+    /// Final size of each exported memory.
+    pub memory: Vec<(String, Pages)>,
+    /// Symbolized backtrace when the guest trapped.
+    pub backtrace: Option<TrapReport>,
+}
+
+impl GuestInstance {
+    pub fn exports(&self) -> &Exports {
+        &self.instance.exports
+    }
+
+    pub fn module_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The runtime profile applied to the guest.
+    pub fn profile(&self) -> &RuntimeProfile {
+        &self.profile
+    }
+
+    /// Exported functions and their signatures, sorted by name.
+    pub fn functions(&self) -> Vec<(String, FunctionType)> {
+        let mut functions: Vec<_> = self
+            .instance
+            .exports
+            .iter()
+            .functions()
+            .map(|(name, function)| (name.clone(), function.ty(&self.store)))
+            .collect();
+        functions.sort_by(|(a, _), (b, _)| a.cmp(b));
+        functions
+    }
+
+    fn function(&self, name: &str) -> Result<Function> {
+        match self.instance.exports.get_function(name) {
+            Ok(function) => Ok(function.clone()),
+            Err(source) => Err(Error::MissingExport {
+                name: name.to_string(),
+                available: self
+                    .functions()
+                    .iter()
+                    .map(|(name, ty)| format_signature(name, ty))
+                    .collect(),
+                source,
+            }),
+        }
+    }
+
+    /// Runs `call` into the guest, stopping it if it runs past the deadline.
+    fn call_with_deadline<R>(
+        &mut self,
+        call: impl FnOnce(&mut Store) -> Result<R, RuntimeError>,
+    ) -> Result<R, RuntimeError> {
+        if let (Some(deadline), Some(timeout)) = (&self.deadline, self.timeout) {
+            deadline.arm(&mut self.store, timeout);
+        }
+        // Only this call's grows count towards its outcome.
+        self.rejected_grows.take();
+        let result = call(&mut self.store);
+        if let Some(deadline) = &self.deadline {
+            deadline.disarm(&mut self.store);
+        }
+        result
+    }
+
+    /// Why a call failed: the deadline, fuel, the memory limit, or what the guest
+    /// did itself.
+    fn failure(&mut self, err: RuntimeError) -> Outcome {
+        if err.is::<DeadlineExceeded>() {
+            Outcome::TimedOut(self.timeout.unwrap_or_default())
+        } else if self.fuel.is_some() && remaining_fuel(&mut self.store, &self.instance).is_none() {
+            Outcome::OutOfFuel
+        } else {
+            self.memory_limit(Outcome::from_call(Err(err)))
+        }
+    }
+
+    /// Blames a failure of the guest on the memory limit when the limit turned down
+    /// one of its grows during the call, which the guest may have reported as
+    /// anything from a trap to an exit code.
+    fn memory_limit(&self, outcome: Outcome) -> Outcome {
+        if matches!(outcome, Outcome::Exited(0) | Outcome::HostError(_)) {
+            return outcome;
+        }
+        let Some(grow) = self.rejected_grows.take().pop() else {
+            return outcome;
+        };
+        // Memories don't know their export names, but the one that hit the limit
+        // can't have grown since.
+        let memory = memory_usage(&self.store, &self.instance)
+            .into_iter()
+            .find(|(_, pages)| *pages == grow.size)
+            .map_or_else(|| "an unexported memory".to_string(), |(name, _)| name);
+        Outcome::MemoryLimit {
+            memory,
+            limit: grow.limit,
+        }
+    }
+
+    /// Calls an export, turning anything other than a normal return into
+    /// [`Error::Guest`]. Each call gets the whole deadline.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Box<[Value]>> {
+        let function = self.function(name)?;
+        self.call_with_deadline(|store| function.call(store, args))
+            .map_err(|err| Error::Guest(self.failure(err)))
+    }
+
+    /// Creates the typed wrapper `wai_bindgen_wasmer::import!` generates for an
+    /// interface the guest exports, by calling its `new` with the guest's exports.
+    ///
+    /// Guests that only export `cabi_realloc` get it under the name the generated
+    /// glue expects, as for [`WaiImports`]. Interfaces with resources aren't
+    /// supported, since their handles must be imported when the guest is created.
+    ///
+    /// ```ignore
+    /// wai_bindgen_wasmer::import!("calculator.wai");
+    /// use calculator::Calculator;
+    ///
+    /// let calculator = instance.bind(|store, exports| {
+    ///     let env = Calculator::add_to_imports(store, &mut Imports::new());
+    ///     Calculator::new(store, exports, env)
+    /// })?;
+    /// let sum = instance.call_bound(|store| calculator.add(store, 1, 2))?;
+    /// ```
+    pub fn bind<T, E: fmt::Display>(
+        &mut self,
+        new: impl FnOnce(&mut Store, &Instance) -> Result<T, E>,
+    ) -> Result<T> {
+        let shim = wai::guest_exports(&mut self.store, &self.instance, &self.bytes)?;
+        let exports = shim.as_ref().unwrap_or(&self.instance);
+        new(&mut self.store, exports).map_err(|err| Error::Bindings(err.to_string()))
+    }
+
+    /// Calls into the guest through bindings made with [`GuestInstance::bind`],
+    /// with the deadline and errors of [`GuestInstance::call`].
+    pub fn call_bound<R>(
+        &mut self,
+        call: impl FnOnce(&mut Store) -> Result<R, RuntimeError>,
+    ) -> Result<R> {
+        self.call_with_deadline(call)
+            .map_err(|err| Error::Guest(self.failure(err)))
+    }
+
+    /// Runs the first of the profile's initializers the guest exports, such as
+    /// `_initialize`, which a library module needs before its exports are called.
+    /// Returns the initializer's name, `None` when the guest exports none.
+    pub fn initialize(&mut self) -> Result<Option<&'static str>> {
+        let initializer = self
+            .profile
+            .initializers
+            .iter()
+            .copied()
+            .find(|name| self.instance.exports.get_function(name).is_ok());
+        if let Some(name) = initializer {
+            self.call(name, &[])?;
+        }
+        Ok(initializer)
+    }
+
+    /// Calls an export with arguments parsed against its signature.
+    pub fn invoke(&mut self, name: &str, args: &[String]) -> Result<Box<[Value]>> {
+        let ty = self.function(name)?.ty(&self.store);
+        let args = parse_args(&ty, args)?;
+        self.call(name, &args)
+    }
+
+    /// Shuts the guest down without running `_start`, e.g. after calling exports.
+    pub fn finish(mut self) -> Result<()> {
+        self.wasi_env.cleanup(&mut self.store, None);
+        self.stdin_task.abort();
+        self.forwarder.finish()
+    }
+
+    /// Runs the entry point, `_start`, to completion and shuts the guest down.
+    pub fn run(mut self) -> Result<RunReport> {
+        let start_func = match self.function(self.profile.entry) {
+            Ok(function) => function,
+            Err(err) => {
+                self.finish()?;
+                return Err(err);
+            }
+        };
+
+        let result = self.call_with_deadline(|store| start_func.call(store, &[]));
+        let remaining = self
+            .fuel
+            .and_then(|_| remaining_fuel(&mut self.store, &self.instance));
+        let memory = memory_usage(&self.store, &self.instance);
+        self.wasi_env.cleanup(&mut self.store, None);
+
+        let outcome = match result {
+            Ok(values) => Outcome::from_call(Ok(values)),
+            Err(err) => self.failure(err),
+        };
+
+        self.stdin_task.abort();
+
+        self.forwarder.finish()?;
+
+        let backtrace = match &outcome {
+            Outcome::Trapped { error, .. } => {
+                Some(Symbolizer::new(&self.bytes).report(error.trace()))
+            }
+            _ => None,
+        };
+
+        Ok(RunReport {
+            outcome,
+            remaining_fuel: remaining,
+            memory,
+            backtrace,
+        })
+    }
+}
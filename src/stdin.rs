@@ -0,0 +1,88 @@
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    runtime::Handle,
+    task::JoinHandle,
+};
+use virtual_fs::Pipe;
+
+use crate::error::Error;
+
+/// Where the guest's stdin comes from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StdinSource {
+    /// The guest sees end-of-file immediately.
+    #[default]
+    Closed,
+    /// Forward the host's stdin, for interactive console apps.
+    Inherit,
+    /// Feed the contents of a file, then end-of-file.
+    File(PathBuf),
+    /// Feed a literal string, then end-of-file.
+    Text(String),
+}
+
+impl FromStr for StdinSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "closed" => return Ok(StdinSource::Closed),
+            "inherit" => return Ok(StdinSource::Inherit),
+            _ => {}
+        }
+        match s.split_once(':') {
+            Some(("file", path)) => Ok(StdinSource::File(PathBuf::from(path))),
+            Some(("text", text)) => Ok(StdinSource::Text(text.to_string())),
+            _ => Err(Error::InvalidArgument(format!(
+                "expected `closed`, `inherit`, `file:<path>` or `text:<string>`, got `{s}`"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for StdinSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StdinSource::Closed => write!(f, "closed"),
+            StdinSource::Inherit => write!(f, "inherit"),
+            StdinSource::File(path) => write!(f, "file:{}", path.display()),
+            StdinSource::Text(text) => write!(f, "text:{text}"),
+        }
+    }
+}
+
+/// Starts feeding `source` into the guest's stdin pipe.
+///
+/// The pipe is dropped once the source is exhausted so the guest sees end-of-file.
+pub fn spawn_stdin(handle: &Handle, source: StdinSource, stdin_tx: Pipe) -> JoinHandle<()> {
+    handle.spawn(async move {
+        if let Err(err) = forward_stdin(&source, stdin_tx).await {
+            log::warn!("Failed to forward stdin ({source}) to the guest: {err}");
+        }
+    })
+}
+
+async fn forward_stdin(source: &StdinSource, mut stdin_tx: Pipe) -> std::io::Result<()> {
+    match source {
+        StdinSource::Closed => Ok(()),
+        StdinSource::Inherit => copy(&mut tokio::io::stdin(), &mut stdin_tx).await,
+        StdinSource::File(path) => {
+            let mut file = tokio::fs::File::open(path).await?;
+            copy(&mut file, &mut stdin_tx).await
+        }
+        StdinSource::Text(text) => {
+            stdin_tx.write_all(text.as_bytes()).await?;
+            stdin_tx.flush().await
+        }
+    }
+}
+
+async fn copy<R>(reader: &mut R, writer: &mut (impl AsyncWrite + Unpin)) -> std::io::Result<()>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    tokio::io::copy(reader, writer).await?;
+    writer.flush().await
+}
@@ -0,0 +1,333 @@
+use std::{
+    fmt::{self, Write as _},
+    fs::File,
+    io::{self, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{io::AsyncReadExt, runtime::Handle, sync::watch, task::JoinHandle};
+use virtual_fs::Pipe;
+
+use crate::error::{Error, Result};
+
+/// How long to keep draining a pipe after the guest has finished.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StreamOptions {
+    /// Text written before every line.
+    pub prefix: Option<String>,
+    /// Prefix every line with the time elapsed since the guest started.
+    pub timestamps: bool,
+    /// File receiving an unmodified copy of the stream.
+    pub tee: Option<PathBuf>,
+}
+
+/// The guest's stdout, for host functions that print on the guest's behalf.
+///
+/// Lines written here go through the same pipe as the guest's own output, so they
+/// are forwarded in the order they were written. Before the guest is instantiated
+/// they go straight to the host's stdout.
+#[derive(Clone, Default)]
+pub struct GuestStdout(Arc<Mutex<Option<Pipe>>>);
+
+impl GuestStdout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn connect(&self, pipe: Pipe) {
+        *self.0.lock().unwrap() = Some(pipe);
+    }
+
+    pub fn println(&self, line: impl fmt::Display) {
+        let line = format!("{line}\n");
+        // A closed pipe only loses the line, as it does for the guest's own output.
+        let _ = match &mut *self.0.lock().unwrap() {
+            Some(pipe) => pipe.write_all(line.as_bytes()),
+            None => io::stdout().lock().write_all(line.as_bytes()),
+        };
+    }
+}
+
+/// Forwards the guest's stdout and stderr pipes to the host console while it runs.
+pub struct StdioForwarder {
+    shutdown: watch::Sender<bool>,
+    tasks: Vec<JoinHandle<io::Result<()>>>,
+}
+
+impl StdioForwarder {
+    pub fn spawn(
+        handle: &Handle,
+        stdout: Pipe,
+        stderr: Pipe,
+        stdout_options: StreamOptions,
+        stderr_options: StreamOptions,
+    ) -> Result<Self> {
+        let started = Instant::now();
+        let (shutdown, shutdown_rx) = watch::channel(false);
+
+        let stdout_sink = LineSink::new(Console::Stdout, stdout_options, started)?;
+        let stderr_sink = LineSink::new(Console::Stderr, stderr_options, started)?;
+
+        let tasks = vec![
+            handle.spawn(forward(stdout, stdout_sink, shutdown_rx.clone())),
+            handle.spawn(forward(stderr, stderr_sink, shutdown_rx)),
+        ];
+
+        Ok(Self { shutdown, tasks })
+    }
+
+    /// Drains whatever the guest wrote last and waits for the forwarders to stop.
+    ///
+    /// This blocks the calling thread without entering the runtime, so it may be
+    /// called from async code, but not on the only thread of a current-thread
+    /// runtime, which the forwarders need to finish.
+    pub fn finish(self) -> Result<()> {
+        let _ = self.shutdown.send(true);
+        for task in self.tasks {
+            futures::executor::block_on(task).map_err(Error::Forwarder)??;
+        }
+        Ok(())
+    }
+}
+
+async fn forward(
+    mut pipe: Pipe,
+    mut sink: LineSink,
+    mut shutdown: watch::Receiver<bool>,
+) -> io::Result<()> {
+    let mut buf = vec![0u8; 8 * 1024];
+    loop {
+        tokio::select! {
+            read = pipe.read(&mut buf) => match read? {
+                0 => return sink.finish(),
+                n => sink.write(&buf[..n])?,
+            },
+            _ = shutdown.changed() => break,
+        }
+    }
+
+    // The guest has exited, pick up anything still queued without waiting for EOF.
+    while let Ok(read) = tokio::time::timeout(DRAIN_TIMEOUT, pipe.read(&mut buf)).await {
+        match read? {
+            0 => break,
+            n => sink.write(&buf[..n])?,
+        }
+    }
+    sink.finish()
+}
+
+/// Writes the guest's output to the console a line at a time, with the prefix and
+/// timestamp in front of each line.
+struct LineSink {
+    output: Box<dyn Write + Send>,
+    options: StreamOptions,
+    tee: Option<File>,
+    /// Bytes held back until more output arrives: an incomplete UTF-8 sequence, or a
+    /// `\r` that may start a `\r\n`.
+    pending: Vec<u8>,
+    /// Whether the next output starts a new line, false after a partial line.
+    line_start: bool,
+    started: Instant,
+}
+
+impl LineSink {
+    fn new(console: Console, options: StreamOptions, started: Instant) -> Result<Self> {
+        let tee = match &options.tee {
+            Some(path) => Some(File::create(path).map_err(Error::file("create", path))?),
+            None => None,
+        };
+        let output: Box<dyn Write + Send> = match console {
+            Console::Stdout => Box::new(io::stdout()),
+            Console::Stderr => Box::new(io::stderr()),
+        };
+        Ok(Self {
+            output,
+            options,
+            tee,
+            pending: Vec::new(),
+            line_start: true,
+            started,
+        })
+    }
+
+    /// Writes what one read of the pipe returned. A trailing partial line, such as a
+    /// prompt, is shown right away rather than once its newline arrives.
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if let Some(tee) = &mut self.tee {
+            tee.write_all(bytes)?;
+        }
+
+        self.pending.extend_from_slice(bytes);
+        while let Some(newline) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=newline).collect();
+            self.emit(&line[..newline], true)?;
+        }
+
+        let mut end = utf8_boundary(&self.pending);
+        if self.pending[..end].ends_with(b"\r") {
+            end -= 1;
+        }
+        if end > 0 {
+            let partial: Vec<u8> = self.pending.drain(..end).collect();
+            self.emit(&partial, false)?;
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, text: &[u8], end_of_line: bool) -> io::Result<()> {
+        let mut out = String::new();
+        if self.line_start {
+            if self.options.timestamps {
+                let elapsed = self.started.elapsed().as_secs_f64();
+                let _ = write!(out, "[{elapsed:>9.3}s] ");
+            }
+            if let Some(prefix) = &self.options.prefix {
+                out.push_str(prefix);
+            }
+        }
+        let text = String::from_utf8_lossy(text);
+        if end_of_line {
+            out.push_str(text.strip_suffix('\r').unwrap_or(&text));
+            out.push('\n');
+        } else {
+            out.push_str(&text);
+        }
+        self.line_start = end_of_line;
+
+        self.output.write_all(out.as_bytes())?;
+        self.output.flush()
+    }
+
+    /// Ends the last line, partial or not, once the guest is done writing.
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() || !self.line_start {
+            let rest = std::mem::take(&mut self.pending);
+            self.emit(&rest, true)?;
+        }
+        if let Some(tee) = &mut self.tee {
+            tee.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Length of the longest prefix of `bytes` that doesn't end in the middle of a
+/// UTF-8 sequence.
+fn utf8_boundary(bytes: &[u8]) -> usize {
+    match std::str::from_utf8(bytes) {
+        Ok(_) => bytes.len(),
+        // An incomplete sequence at the end; keep it until the rest arrives.
+        Err(err) if err.error_len().is_none() => err.valid_up_to(),
+        // Invalid bytes are rendered lossily, there is nothing to wait for.
+        Err(_) => bytes.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Console output a test can read back.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn sink(prefix: Option<&str>) -> (LineSink, Output) {
+        let output = Output::default();
+        let sink = LineSink {
+            output: Box::new(output.clone()),
+            options: StreamOptions {
+                prefix: prefix.map(str::to_string),
+                ..StreamOptions::default()
+            },
+            tee: None,
+            pending: Vec::new(),
+            line_start: true,
+            started: Instant::now(),
+        };
+        (sink, output)
+    }
+
+    #[test]
+    fn utf8_boundary_holds_back_incomplete_sequences() {
+        let euro = "a€".as_bytes();
+        assert_eq!(utf8_boundary(b""), 0);
+        assert_eq!(utf8_boundary(b"abc"), 3);
+        assert_eq!(utf8_boundary(euro), 4);
+        assert_eq!(utf8_boundary(&euro[..2]), 1);
+        assert_eq!(utf8_boundary(&euro[..3]), 1);
+        // Invalid bytes never become valid, so they aren't held back.
+        assert_eq!(utf8_boundary(b"a\xff"), 2);
+    }
+
+    #[test]
+    fn prefixes_each_line() {
+        let (mut sink, output) = sink(Some("[guest] "));
+        sink.write(b"one\ntwo\n").unwrap();
+        assert_eq!(output.text(), "[guest] one\n[guest] two\n");
+    }
+
+    #[test]
+    fn shows_partial_lines_right_away() {
+        let (mut sink, output) = sink(Some("[guest] "));
+        sink.write(b"Name: ").unwrap();
+        assert_eq!(output.text(), "[guest] Name: ");
+        sink.write(b"Ada\nHi").unwrap();
+        assert_eq!(output.text(), "[guest] Name: Ada\n[guest] Hi");
+    }
+
+    #[test]
+    fn strips_carriage_returns_split_across_reads() {
+        let (mut sink, output) = sink(None);
+        sink.write(b"one\r").unwrap();
+        assert_eq!(output.text(), "one");
+        sink.write(b"\ntwo\r\n").unwrap();
+        assert_eq!(output.text(), "one\ntwo\n");
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_utf8_sequence() {
+        let (mut sink, output) = sink(None);
+        let bytes = "é\n".as_bytes();
+        sink.write(&bytes[..1]).unwrap();
+        assert_eq!(output.text(), "");
+        sink.write(&bytes[1..]).unwrap();
+        assert_eq!(output.text(), "é\n");
+    }
+
+    #[test]
+    fn finish_ends_the_last_line() {
+        let (mut sink, output) = sink(None);
+        sink.finish().unwrap();
+        assert_eq!(output.text(), "");
+        sink.write(b"no newline").unwrap();
+        sink.finish().unwrap();
+        assert_eq!(output.text(), "no newline\n");
+    }
+}
@@ -0,0 +1,455 @@
+use std::{collections::HashMap, fmt};
+
+use wasmer::FrameInfo;
+use wasmparser::{Name, NameSectionReader, Naming, Parser, Payload};
+
+use crate::dwarf::{LineTable, SourceLocation};
+
+/// Assembly prefixes NativeAOT uses for the framework's private assemblies.
+const KNOWN_ASSEMBLIES: &[&str] = &[
+    "S_P_CoreLib",
+    "S_P_TypeLoader",
+    "S_P_Reflection_Execution",
+    "S_P_StackTraceMetadata",
+    "System_Console",
+    "Internal_CompilerGenerated",
+];
+
+/// CoreLib types NativeAOT mangles without an assembly prefix.
+const PRIMITIVE_TYPES: &[&str] = &[
+    "Object", "String", "Bool", "Char", "Int8", "UInt8", "Int16", "UInt16", "Int32", "UInt32",
+    "Int64", "UInt64", "IntPtr", "UIntPtr", "Single", "Double", "Void",
+];
+
+/// Resolves trap frames to readable names using the module's name section, and
+/// to source lines when it carries DWARF.
+#[derive(Debug, Default)]
+pub struct Symbolizer {
+    names: HashMap<u32, String>,
+    /// Offset of the code section's contents, which DWARF addresses are relative to.
+    code_offset: usize,
+    lines: Option<LineTable>,
+    /// Mangled assembly prefixes, longest first so nested names match correctly.
+    assemblies: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameKind {
+    /// A compiled C# method.
+    Managed { assembly: String, method: String },
+    /// Native code linked into the module: the GC, thread store, ICU, libc...
+    Runtime(String),
+    /// A function the name section doesn't cover.
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// Position in the trace, 0 being the innermost frame.
+    pub depth: usize,
+    pub func_index: u32,
+    pub module_offset: usize,
+    pub kind: FrameKind,
+    pub location: Option<SourceLocation>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TrapReport {
+    pub frames: Vec<Frame>,
+    pub has_debug_info: bool,
+}
+
+impl Symbolizer {
+    pub fn new(bytes: &[u8]) -> Self {
+        let sections = parse_sections(bytes).unwrap_or_default();
+        let names = sections.names;
+        let lines = match LineTable::parse(&sections.debug) {
+            Ok(lines) => lines,
+            Err(err) => {
+                log::warn!("Ignoring malformed DWARF in the module: {err}");
+                None
+            }
+        };
+
+        let mut assemblies: Vec<String> = KNOWN_ASSEMBLIES.iter().map(|a| a.to_string()).collect();
+        for name in names.values() {
+            // Every NativeAOT assembly carries a `<Module>` type with its initializers.
+            if let Some((assembly, _)) = name.split_once("__Module___") {
+                if !assembly.is_empty() && !assemblies.iter().any(|a| a == assembly) {
+                    assemblies.push(assembly.to_string());
+                }
+            }
+        }
+        assemblies.sort_by_key(|a| std::cmp::Reverse(a.len()));
+
+        Self {
+            names,
+            code_offset: sections.code_offset,
+            lines,
+            assemblies,
+        }
+    }
+
+    pub fn has_debug_info(&self) -> bool {
+        self.lines.is_some()
+    }
+
+    pub fn source_location(&self, module_offset: usize) -> Option<SourceLocation> {
+        let address = module_offset.checked_sub(self.code_offset)?;
+        self.lines.as_ref()?.lookup(address as u64)
+    }
+
+    pub fn function_name<'a>(&'a self, frame: &'a FrameInfo) -> Option<&'a str> {
+        self.names
+            .get(&frame.func_index())
+            .map(String::as_str)
+            .or_else(|| frame.function_name())
+    }
+
+    pub fn symbolize(&self, depth: usize, frame: &FrameInfo) -> Frame {
+        let kind = match self.function_name(frame) {
+            Some(name) => match self.demangle(name) {
+                Some((assembly, method)) => FrameKind::Managed { assembly, method },
+                None => FrameKind::Runtime(name.to_string()),
+            },
+            None => FrameKind::Unknown,
+        };
+        Frame {
+            depth,
+            func_index: frame.func_index(),
+            module_offset: frame.module_offset(),
+            kind,
+            location: self.source_location(frame.module_offset()),
+        }
+    }
+
+    pub fn report(&self, trace: &[FrameInfo]) -> TrapReport {
+        TrapReport {
+            frames: trace
+                .iter()
+                .enumerate()
+                .map(|(depth, frame)| self.symbolize(depth, frame))
+                .collect(),
+            has_debug_info: self.has_debug_info(),
+        }
+    }
+
+    /// Turns a NativeAOT symbol such as `S_P_CoreLib_System_Threading_Monitor__Enter`
+    /// into its assembly and C# name, `System.Private.CoreLib` and
+    /// `System.Threading.Monitor.Enter`. Returns `None` for non-managed symbols.
+    pub fn demangle(&self, symbol: &str) -> Option<(String, String)> {
+        if symbol.contains("::") || symbol.contains('(') {
+            return None;
+        }
+        // Boxing and unboxing thunks forward to the method on the value type.
+        let symbol = symbol
+            .strip_prefix("<Boxed>")
+            .or_else(|| symbol.strip_prefix("unbox_"))
+            .unwrap_or(symbol);
+        // `System.__Canon` stands in for reference types in shared generic code; hide
+        // its underscores from the separators below.
+        let symbol = symbol.replace("System___Canon", "System.$Canon");
+
+        let (assembly, rest) = match self.assemblies.iter().find_map(|a| {
+            Some((
+                a.as_str(),
+                symbol.strip_prefix(a.as_str())?.strip_prefix('_')?,
+            ))
+        }) {
+            Some((assembly, rest)) => match rest.strip_prefix("_Module_") {
+                Some(rest) => (assembly, format!("<Module>{rest}")),
+                None => (assembly, rest.to_string()),
+            },
+            None => {
+                let (ty, _) = symbol.split_once("__")?;
+                if !PRIMITIVE_TYPES.contains(&ty) {
+                    return None;
+                }
+                ("S_P_CoreLib", format!("System_{symbol}"))
+            }
+        };
+
+        let (ty, method) = split_once_outside_generics(&rest, "__")?;
+        let method = match method.strip_prefix("<unbox>") {
+            Some(unboxed) => split_once_outside_generics(unboxed, "__").map_or(method, |(_, m)| m),
+            None => method,
+        };
+        let ty = demangle_type(ty, &self.assemblies);
+        let method = demangle_method(method);
+
+        Some((assembly_name(assembly), format!("{ty}.{method}")))
+    }
+}
+
+impl TrapReport {
+    pub fn managed(&self) -> impl Iterator<Item = &Frame> {
+        self.frames
+            .iter()
+            .filter(|f| matches!(f.kind, FrameKind::Managed { .. }))
+    }
+
+    pub fn runtime(&self) -> impl Iterator<Item = &Frame> {
+        self.frames
+            .iter()
+            .filter(|f| !matches!(f.kind, FrameKind::Managed { .. }))
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:<3} ", self.depth)?;
+        match &self.kind {
+            FrameKind::Managed { assembly, method } => write!(f, "{method} in {assembly}"),
+            FrameKind::Runtime(name) => write!(f, "{name}"),
+            FrameKind::Unknown => write!(f, "<func[{}]>", self.func_index),
+        }?;
+        match &self.location {
+            Some(location) => write!(f, " at {location}"),
+            None => write!(f, " @ {:#x}", self.module_offset),
+        }
+    }
+}
+
+impl fmt::Display for TrapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.frames.is_empty() {
+            return writeln!(f, "No backtrace available");
+        }
+        writeln!(f, "Managed frames:")?;
+        let mut any = false;
+        for frame in self.managed() {
+            writeln!(f, "  {frame}")?;
+            any = true;
+        }
+        if !any {
+            writeln!(f, "  (none)")?;
+        }
+        writeln!(f, "Runtime frames:")?;
+        for frame in self.runtime() {
+            writeln!(f, "  {frame}")?;
+        }
+        if !self.has_debug_info {
+            writeln!(f, "(the module has no DWARF line information)")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Sections<'a> {
+    names: HashMap<u32, String>,
+    code_offset: usize,
+    debug: HashMap<&'a str, &'a [u8]>,
+}
+
+fn parse_sections(bytes: &[u8]) -> wasmparser::Result<Sections<'_>> {
+    let mut sections = Sections::default();
+    for payload in Parser::new(0).parse_all(bytes) {
+        match payload? {
+            Payload::CodeSectionStart { range, .. } => sections.code_offset = range.start,
+            Payload::CustomSection(section) if section.name().starts_with(".debug_") => {
+                sections.debug.insert(section.name(), section.data());
+            }
+            Payload::CustomSection(section) if section.name() == "name" => {
+                let mut reader = NameSectionReader::new(section.data(), section.data_offset())?;
+                while !reader.eof() {
+                    let Name::Function(map) = reader.read()? else {
+                        continue;
+                    };
+                    for naming in map {
+                        let Naming { index, name } = naming?;
+                        sections.names.insert(index, name.to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(sections)
+}
+
+/// Splits on `separator`, ignoring occurrences inside `<...>`.
+fn split_outside_generics<'a>(s: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut rest = s;
+    while let Some((head, tail)) = split_once_outside_generics(rest, separator) {
+        parts.push(head);
+        rest = tail;
+    }
+    parts.push(rest);
+    parts
+}
+
+fn split_once_outside_generics<'a>(s: &'a str, separator: &str) -> Option<(&'a str, &'a str)> {
+    let mut depth = 0usize;
+    for (i, b) in s.bytes().enumerate() {
+        match b {
+            b'<' => depth += 1,
+            b'>' => depth = depth.saturating_sub(1),
+            _ if depth == 0 && i > 0 && s.as_bytes()[i..].starts_with(separator.as_bytes()) => {
+                return Some((&s[..i], &s[i + separator.len()..]));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn demangle_type(ty: &str, assemblies: &[String]) -> String {
+    let (name, args) = match ty.split_once('<') {
+        Some((name, args)) if ty.ends_with('>') && !name.is_empty() => {
+            (name, Some(&args[..args.len() - 1]))
+        }
+        _ => (ty, None),
+    };
+
+    // Generic types carry their arity, e.g. `EqualityComparer_1<UInt16>`.
+    let name = match (args, name.rsplit_once('_')) {
+        (Some(_), Some((base, arity))) if arity.chars().all(|c| c.is_ascii_digit()) => base,
+        _ => name,
+    };
+    let mut out = name.replace('_', ".").replace('$', "__");
+
+    if let Some(args) = args {
+        let args: Vec<String> = split_outside_generics(args, "__")
+            .into_iter()
+            .map(|arg| {
+                let arg = assemblies
+                    .iter()
+                    .find_map(|a| arg.strip_prefix(a.as_str())?.strip_prefix('_'))
+                    .unwrap_or(arg);
+                demangle_type(arg, assemblies)
+            })
+            .collect();
+        out.push('<');
+        out.push_str(&args.join(", "));
+        out.push('>');
+    }
+    out
+}
+
+fn demangle_method(method: &str) -> String {
+    match method {
+        "_Main__" => return "<Main>$".to_string(),
+        "_ctor" => return ".ctor".to_string(),
+        "_cctor" => return ".cctor".to_string(),
+        _ => {}
+    }
+
+    // Overloads get a numeric suffix, e.g. `GetBytes_1`.
+    let method = match method.rsplit_once('_') {
+        Some((base, n)) if !base.is_empty() && n.chars().all(|c| c.is_ascii_digit()) => base,
+        _ => method,
+    };
+
+    // Explicit interface implementations are prefixed with the interface's full name.
+    if ["System_", "Internal_", "Microsoft_"]
+        .iter()
+        .any(|ns| method.starts_with(ns))
+    {
+        method.replace('_', ".")
+    } else {
+        method.to_string()
+    }
+}
+
+fn assembly_name(mangled: &str) -> String {
+    match mangled.strip_prefix("S_P_") {
+        Some(rest) => format!("System.Private.{}", rest.replace('_', ".")),
+        None => mangled.replace('_', "."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A symbolizer that knows the framework's assemblies and `extra` ones, as if
+    /// they had been found in a name section.
+    fn symbolizer(extra: &[&str]) -> Symbolizer {
+        let mut symbolizer = Symbolizer::new(&[]);
+        symbolizer
+            .assemblies
+            .extend(extra.iter().map(|a| a.to_string()));
+        symbolizer
+            .assemblies
+            .sort_by_key(|a| std::cmp::Reverse(a.len()));
+        symbolizer
+    }
+
+    fn demangle(symbol: &str) -> Option<(String, String)> {
+        symbolizer(&["cswasi"]).demangle(symbol)
+    }
+
+    fn managed(assembly: &str, method: &str) -> Option<(String, String)> {
+        Some((assembly.to_string(), method.to_string()))
+    }
+
+    #[test]
+    fn demangles_corelib_methods() {
+        assert_eq!(
+            demangle("S_P_CoreLib_System_Threading_Monitor__Enter"),
+            managed("System.Private.CoreLib", "System.Threading.Monitor.Enter")
+        );
+    }
+
+    #[test]
+    fn demangles_the_entry_point() {
+        assert_eq!(
+            demangle("cswasi_Program___Main__"),
+            managed("cswasi", "Program.<Main>$")
+        );
+    }
+
+    #[test]
+    fn demangles_generic_instantiations() {
+        assert_eq!(
+            demangle("S_P_CoreLib_System_Collections_Generic_List_1<System___Canon>__Add"),
+            managed(
+                "System.Private.CoreLib",
+                "System.Collections.Generic.List<System.__Canon>.Add"
+            )
+        );
+        assert_eq!(
+            demangle(
+                "S_P_CoreLib_System_Collections_Generic_Dictionary_2<S_P_CoreLib_System_String__Int32>__TryGetValue"
+            ),
+            managed(
+                "System.Private.CoreLib",
+                "System.Collections.Generic.Dictionary<System.String, Int32>.TryGetValue"
+            )
+        );
+    }
+
+    #[test]
+    fn demangles_boxing_thunks() {
+        let expected = managed("System.Private.CoreLib", "System.Int32.GetHashCode");
+        assert_eq!(
+            demangle("<Boxed>S_P_CoreLib_System_Int32__GetHashCode"),
+            expected
+        );
+        assert_eq!(
+            demangle("unbox_S_P_CoreLib_System_Int32__GetHashCode"),
+            expected
+        );
+        assert_eq!(
+            demangle("S_P_CoreLib_System_Int32__<unbox>S_P_CoreLib_System_Int32__GetHashCode"),
+            expected
+        );
+    }
+
+    #[test]
+    fn demangles_non_ascii_names() {
+        assert_eq!(
+            demangle("cswasi_Größe__Bérechnen"),
+            managed("cswasi", "Größe.Bérechnen")
+        );
+    }
+
+    #[test]
+    fn leaves_runtime_symbols_alone() {
+        assert_eq!(demangle("RhpNewFast"), None);
+        assert_eq!(demangle("memcpy"), None);
+        assert_eq!(demangle("std::panicking::begin_panic"), None);
+    }
+}
@@ -0,0 +1,365 @@
+use std::{
+    ptr::NonNull,
+    sync::{Arc, Mutex},
+};
+
+use wasmer::{
+    vm::{
+        LinearMemory, MemoryError, MemoryStyle, TableStyle, VMMemory, VMMemoryDefinition, VMTable,
+        VMTableDefinition,
+    },
+    AsStoreRef, BaseTunables, Instance, MemoryType, Pages, TableType, Target, Tunables,
+};
+use wasmparser::{Parser, Payload, TypeRef};
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Maximum size of each linear memory, in 64 KiB pages.
+    pub memory_pages: Option<u32>,
+    /// Maximum number of elements in each table.
+    pub table_elements: Option<u32>,
+    /// Maximum number of linear memories a module may define or import.
+    pub memories: Option<u32>,
+}
+
+impl ResourceLimits {
+    pub fn is_unlimited(&self) -> bool {
+        *self == ResourceLimits::default()
+    }
+
+    /// Identifies limits that change how memories are compiled, for the module cache.
+    pub fn cache_tag(&self) -> String {
+        match (self.memory_pages, self.table_elements) {
+            (None, None) => String::new(),
+            (pages, elements) => format!("limits:{pages:?}:{elements:?}"),
+        }
+    }
+
+    /// Checks the limits that can be decided from the module alone.
+    pub fn check_module(&self, bytes: &[u8]) -> Result<()> {
+        let (memories, minimums) = memory_declarations(bytes)?;
+        if let Some(max) = self.memories {
+            if memories > max {
+                return Err(Error::Limit(format!(
+                    "the module declares {memories} memories, but at most {max} are allowed"
+                )));
+            }
+        }
+        if let Some(max) = self.memory_pages {
+            if let Some(minimum) = minimums
+                .into_iter()
+                .find(|&minimum| minimum > u64::from(max))
+            {
+                return Err(Error::Limit(format!(
+                    "the module needs at least {minimum} memory pages, but the limit is {max}"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A `memory.grow` that failed because of the page limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RejectedGrow {
+    /// Size of the memory when it tried to grow.
+    pub size: Pages,
+    pub limit: Pages,
+}
+
+/// Where [`LimitingTunables`] records the grows it turned down, shared with the
+/// memories it creates.
+#[derive(Debug, Clone, Default)]
+pub struct RejectedGrows(Arc<Mutex<Vec<RejectedGrow>>>);
+
+impl RejectedGrows {
+    fn record(&self, grow: RejectedGrow) {
+        self.0.lock().unwrap().push(grow);
+    }
+
+    /// The grows rejected since the last call.
+    pub fn take(&self) -> Vec<RejectedGrow> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+/// Tunables that clamp memory and table maximums to the configured limits.
+///
+/// Growing past a clamped maximum makes `memory.grow`/`table.grow` return -1, which
+/// the .NET runtimes turn into an out-of-memory failure. Memory grows that fail
+/// because of the limit are recorded in [`RejectedGrows`], so the failure can be
+/// told apart from others later.
+pub struct LimitingTunables<T: Tunables> {
+    limits: ResourceLimits,
+    base: T,
+    rejected: RejectedGrows,
+}
+
+impl LimitingTunables<BaseTunables> {
+    pub fn for_host(limits: ResourceLimits, rejected: RejectedGrows) -> Self {
+        Self::new(
+            BaseTunables::for_target(&Target::default()),
+            limits,
+            rejected,
+        )
+    }
+}
+
+impl<T: Tunables> LimitingTunables<T> {
+    pub fn new(base: T, limits: ResourceLimits, rejected: RejectedGrows) -> Self {
+        Self {
+            limits,
+            base,
+            rejected,
+        }
+    }
+
+    /// Wraps memories whose maximum the limit lowered, to record their failed grows.
+    /// Shared memories are left alone, their atomics can't be forwarded.
+    fn record_grows(&self, requested: &MemoryType, memory: VMMemory) -> VMMemory {
+        match self.limits.memory_pages.map(Pages) {
+            Some(limit) if !requested.shared && requested.maximum.is_none_or(|max| max > limit) => {
+                VMMemory(Box::new(LimitedMemory {
+                    inner: memory,
+                    limit,
+                    rejected: self.rejected.clone(),
+                }))
+            }
+            _ => memory,
+        }
+    }
+
+    fn adjust_memory(&self, requested: &MemoryType) -> MemoryType {
+        let mut adjusted = *requested;
+        if let Some(limit) = self.limits.memory_pages.map(Pages) {
+            adjusted.maximum = Some(requested.maximum.map_or(limit, |max| max.min(limit)));
+        }
+        adjusted
+    }
+
+    fn validate_memory(&self, ty: &MemoryType) -> Result<(), MemoryError> {
+        match self.limits.memory_pages {
+            Some(limit) if ty.minimum > Pages(limit) => Err(MemoryError::Generic(format!(
+                "memory needs {} pages, but the limit is {limit}",
+                ty.minimum.0
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn adjust_table(&self, requested: &TableType) -> TableType {
+        let mut adjusted = *requested;
+        if let Some(limit) = self.limits.table_elements {
+            adjusted.maximum = Some(requested.maximum.map_or(limit, |max| max.min(limit)));
+        }
+        adjusted
+    }
+
+    fn validate_table(&self, ty: &TableType) -> Result<(), String> {
+        match self.limits.table_elements {
+            Some(limit) if ty.minimum > limit => Err(format!(
+                "table needs {} elements, but the limit is {limit}",
+                ty.minimum
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<T: Tunables> Tunables for LimitingTunables<T> {
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        self.base.memory_style(&self.adjust_memory(memory))
+    }
+
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.base.table_style(&self.adjust_table(table))
+    }
+
+    fn create_host_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+    ) -> Result<VMMemory, MemoryError> {
+        let adjusted = self.adjust_memory(ty);
+        self.validate_memory(&adjusted)?;
+        let memory = self.base.create_host_memory(&adjusted, style)?;
+        Ok(self.record_grows(ty, memory))
+    }
+
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<VMMemory, MemoryError> {
+        let adjusted = self.adjust_memory(ty);
+        self.validate_memory(&adjusted)?;
+        let memory = self
+            .base
+            .create_vm_memory(&adjusted, style, vm_definition_location)?;
+        Ok(self.record_grows(ty, memory))
+    }
+
+    fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<VMTable, String> {
+        let adjusted = self.adjust_table(ty);
+        self.validate_table(&adjusted)?;
+        self.base.create_host_table(&adjusted, style)
+    }
+
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<VMTable, String> {
+        let adjusted = self.adjust_table(ty);
+        self.validate_table(&adjusted)?;
+        self.base
+            .create_vm_table(&adjusted, style, vm_definition_location)
+    }
+}
+
+/// A memory that records grows past `limit` before failing them.
+#[derive(Debug)]
+struct LimitedMemory {
+    inner: VMMemory,
+    limit: Pages,
+    rejected: RejectedGrows,
+}
+
+impl LimitedMemory {
+    fn wrap(&self, inner: Box<dyn LinearMemory>) -> Box<dyn LinearMemory> {
+        Box::new(LimitedMemory {
+            inner: VMMemory(inner),
+            limit: self.limit,
+            rejected: self.rejected.clone(),
+        })
+    }
+}
+
+impl LinearMemory for LimitedMemory {
+    fn ty(&self) -> MemoryType {
+        self.inner.ty()
+    }
+
+    fn size(&self) -> Pages {
+        self.inner.size()
+    }
+
+    fn style(&self) -> MemoryStyle {
+        self.inner.style()
+    }
+
+    fn grow(&mut self, delta: Pages) -> Result<Pages, MemoryError> {
+        let size = self.inner.size();
+        self.inner.grow(delta).inspect_err(|_| {
+            if u64::from(size.0) + u64::from(delta.0) > u64::from(self.limit.0) {
+                self.rejected.record(RejectedGrow {
+                    size,
+                    limit: self.limit,
+                });
+            }
+        })
+    }
+
+    fn vmmemory(&self) -> NonNull<VMMemoryDefinition> {
+        self.inner.vmmemory()
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn LinearMemory>, MemoryError> {
+        Ok(self.wrap(self.inner.try_clone()?))
+    }
+
+    fn copy(&mut self) -> Result<Box<dyn LinearMemory>, MemoryError> {
+        let copy = self.inner.copy()?;
+        Ok(self.wrap(copy))
+    }
+}
+
+/// Size of each exported memory. Memories never shrink, so after a run this is
+/// also the peak size.
+pub fn memory_usage(store: &impl AsStoreRef, instance: &Instance) -> Vec<(String, Pages)> {
+    instance
+        .exports
+        .iter()
+        .memories()
+        .map(|(name, memory)| (name.clone(), memory.view(store).size()))
+        .collect()
+}
+
+/// Number of memories the module defines or imports, and their minimum sizes.
+fn memory_declarations(bytes: &[u8]) -> Result<(u32, Vec<u64>)> {
+    let mut count = 0;
+    let mut minimums = Vec::new();
+    for payload in Parser::new(0).parse_all(bytes) {
+        match payload? {
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    if let TypeRef::Memory(memory) = import?.ty {
+                        count += 1;
+                        minimums.push(memory.initial);
+                    }
+                }
+            }
+            Payload::MemorySection(reader) => {
+                for memory in reader {
+                    count += 1;
+                    minimums.push(memory?.initial);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok((count, minimums))
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmer::{imports, Cranelift, EngineBuilder, Module, Store, Value};
+
+    use super::*;
+
+    fn grow(wat: &str, delta: i32) -> (i32, Vec<RejectedGrow>) {
+        let rejected = RejectedGrows::default();
+        let limits = ResourceLimits {
+            memory_pages: Some(2),
+            ..ResourceLimits::default()
+        };
+        let mut engine = EngineBuilder::new(Cranelift::default()).engine();
+        engine.set_tunables(LimitingTunables::for_host(limits, rejected.clone()));
+        let mut store = Store::new(engine);
+        let module = Module::new(&store, wat).unwrap();
+        let instance = Instance::new(&mut store, &module, &imports! {}).unwrap();
+
+        let grow = instance.exports.get_function("grow").unwrap();
+        let result = grow.call(&mut store, &[Value::I32(delta)]).unwrap();
+        (result[0].unwrap_i32(), rejected.take())
+    }
+
+    const UNBOUNDED: &str = r#"(module
+        (memory 1)
+        (func (export "grow") (param i32) (result i32) local.get 0 memory.grow))"#;
+
+    #[test]
+    fn records_grows_past_the_limit() {
+        assert_eq!(grow(UNBOUNDED, 1), (1, vec![]));
+        assert_eq!(
+            grow(UNBOUNDED, 2),
+            (
+                -1,
+                vec![RejectedGrow {
+                    size: Pages(1),
+                    limit: Pages(2),
+                }]
+            )
+        );
+    }
+
+    #[test]
+    fn leaves_the_module_maximum_to_the_module() {
+        let bounded = UNBOUNDED.replace("(memory 1)", "(memory 1 1)");
+        assert_eq!(grow(&bounded, 2), (-1, vec![]));
+    }
+}
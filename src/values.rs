@@ -0,0 +1,193 @@
+//! Converting between command-line text and WebAssembly values.
+
+use std::fmt::Write as _;
+
+use wasmer::{FunctionType, Type, Value};
+
+use crate::error::{Error, Result};
+
+/// Parses one argument per parameter of `ty`.
+pub fn parse_args(ty: &FunctionType, args: &[String]) -> Result<Vec<Value>> {
+    if args.len() != ty.params().len() {
+        return Err(Error::InvalidArgument(format!(
+            "expected {} arguments for {ty}, got {}",
+            ty.params().len(),
+            args.len()
+        )));
+    }
+    ty.params()
+        .iter()
+        .zip(args)
+        .map(|(ty, arg)| parse_value(*ty, arg))
+        .collect()
+}
+
+/// Parses a value of type `ty`.
+///
+/// Integers may be negative or unsigned, in decimal or with a `0x` prefix; their bits
+/// are reinterpreted as the signed type. Floats accept `nan` and `inf`. References
+/// only accept `null`.
+pub fn parse_value(ty: Type, s: &str) -> Result<Value> {
+    let invalid = || Error::InvalidArgument(format!("`{s}` is not a valid {}", type_name(ty)));
+    let value = match ty {
+        Type::I32 => Value::I32(parse_int(s, u32::MAX.into()).ok_or_else(invalid)? as u32 as i32),
+        Type::I64 => Value::I64(parse_int(s, u64::MAX.into()).ok_or_else(invalid)? as u64 as i64),
+        Type::F32 => Value::F32(s.parse().map_err(|_| invalid())?),
+        Type::F64 => Value::F64(s.parse().map_err(|_| invalid())?),
+        Type::V128 => Value::V128(parse_int(s, u128::MAX).ok_or_else(invalid)?),
+        Type::ExternRef if s == "null" => Value::ExternRef(None),
+        Type::FuncRef if s == "null" => Value::FuncRef(None),
+        Type::ExternRef | Type::FuncRef => return Err(invalid()),
+    };
+    Ok(value)
+}
+
+/// Parses an integer that fits in `max` as unsigned, or in its signed half when
+/// negative, returning its two's complement bits.
+fn parse_int(s: &str, max: u128) -> Option<u128> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let magnitude = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => u128::from_str_radix(&hex.replace('_', ""), 16).ok()?,
+        None => digits.replace('_', "").parse::<u128>().ok()?,
+    };
+    if !negative {
+        return (magnitude <= max).then_some(magnitude);
+    }
+    let half = max / 2 + 1;
+    (magnitude <= half).then(|| magnitude.wrapping_neg() & max)
+}
+
+pub fn type_name(ty: Type) -> &'static str {
+    match ty {
+        Type::I32 => "i32",
+        Type::I64 => "i64",
+        Type::F32 => "f32",
+        Type::F64 => "f64",
+        Type::V128 => "v128",
+        Type::ExternRef => "externref",
+        Type::FuncRef => "funcref",
+    }
+}
+
+/// Formats a value the way [`parse_value`] reads it back.
+pub fn format_value(value: &Value) -> String {
+    match value {
+        Value::I32(v) => v.to_string(),
+        Value::I64(v) => v.to_string(),
+        Value::F32(v) => v.to_string(),
+        Value::F64(v) => v.to_string(),
+        Value::V128(v) => format!("{v:#034x}"),
+        Value::ExternRef(None) | Value::FuncRef(None) => "null".to_string(),
+        Value::ExternRef(Some(_)) => "<externref>".to_string(),
+        Value::FuncRef(Some(_)) => "<funcref>".to_string(),
+    }
+}
+
+/// `name(i32, f64) -> i64`, for listing exports.
+pub fn format_signature(name: &str, ty: &FunctionType) -> String {
+    let list = |types: &[Type]| {
+        types
+            .iter()
+            .map(|ty| type_name(*ty))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let mut signature = format!("{name}({})", list(ty.params()));
+    match ty.results() {
+        [] => {}
+        [result] => {
+            let _ = write!(signature, " -> {}", type_name(*result));
+        }
+        results => {
+            let _ = write!(signature, " -> ({})", list(results));
+        }
+    }
+    signature
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(ty: Type, s: &str) -> Option<Value> {
+        parse_value(ty, s).ok()
+    }
+
+    #[test]
+    fn parses_signed_and_unsigned_integers() {
+        assert!(matches!(parse(Type::I32, "42"), Some(Value::I32(42))));
+        assert!(matches!(parse(Type::I32, "-1"), Some(Value::I32(-1))));
+        assert!(matches!(
+            parse(Type::I32, "4294967295"),
+            Some(Value::I32(-1))
+        ));
+        assert!(matches!(
+            parse(Type::I32, "-2147483648"),
+            Some(Value::I32(i32::MIN))
+        ));
+        assert!(matches!(
+            parse(Type::I32, "0xff_ff"),
+            Some(Value::I32(0xffff))
+        ));
+        assert!(matches!(parse(Type::I32, "-0x10"), Some(Value::I32(-16))));
+        assert!(matches!(
+            parse(Type::I64, "1_000_000"),
+            Some(Value::I64(1_000_000))
+        ));
+        assert!(matches!(
+            parse(Type::I64, "18446744073709551615"),
+            Some(Value::I64(-1))
+        ));
+        assert!(matches!(parse(Type::V128, "0x1"), Some(Value::V128(1))));
+    }
+
+    #[test]
+    fn rejects_integers_out_of_range() {
+        assert!(parse(Type::I32, "4294967296").is_none());
+        assert!(parse(Type::I32, "-2147483649").is_none());
+        assert!(parse(Type::I64, "-9223372036854775809").is_none());
+        assert!(parse(Type::I32, "1.5").is_none());
+        assert!(parse(Type::I32, "").is_none());
+        assert!(parse(Type::I32, "0x").is_none());
+    }
+
+    #[test]
+    fn parses_floats() {
+        assert!(matches!(parse(Type::F32, "1.5"), Some(Value::F32(v)) if v == 1.5));
+        assert!(matches!(parse(Type::F64, "-2e3"), Some(Value::F64(v)) if v == -2000.0));
+        assert!(matches!(parse(Type::F64, "inf"), Some(Value::F64(v)) if v == f64::INFINITY));
+        assert!(matches!(parse(Type::F32, "nan"), Some(Value::F32(v)) if v.is_nan()));
+        assert!(parse(Type::F64, "one").is_none());
+    }
+
+    #[test]
+    fn references_only_accept_null() {
+        assert!(matches!(
+            parse(Type::ExternRef, "null"),
+            Some(Value::ExternRef(None))
+        ));
+        assert!(matches!(
+            parse(Type::FuncRef, "null"),
+            Some(Value::FuncRef(None))
+        ));
+        assert!(parse(Type::FuncRef, "0").is_none());
+    }
+
+    #[test]
+    fn formatted_values_parse_back() {
+        for (ty, text) in [
+            (Type::I32, "-7"),
+            (Type::I64, "9007199254740993"),
+            (Type::F64, "0.1"),
+        ] {
+            let value = parse_value(ty, text).unwrap();
+            assert_eq!(format_value(&value), text);
+        }
+    }
+}
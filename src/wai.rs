@@ -0,0 +1,469 @@
+//! Guest imports implemented through typed traits generated from `.wai` interface files.
+//!
+//! `wai_bindgen_wasmer::export!` turns each file in `wit/` into a module with a trait
+//! for the host to implement and an `add_to_imports` function that marshals strings,
+//! lists, records and variants across the canonical ABI.
+//!
+//! For interfaces the guest exports, `wai_bindgen_wasmer::import!` generates a struct
+//! with a typed method per function; [`GuestInstance::bind`] creates it and
+//! [`GuestInstance::call_bound`] calls through it. Interface files are read when the
+//! host is compiled, not at runtime: a host can only marshal the types of interfaces
+//! it was built with, and calls to other exports go through untyped values.
+//!
+//! The host side of `wit/strings.wai` is written by hand in [`strings`]: the glue
+//! `export!` generates for string parameters keeps a view of guest memory alive
+//! across `store.data_mut()`, which wasmer 4 rejects.
+//!
+//! [`GuestInstance::bind`]: crate::GuestInstance::bind
+//! [`GuestInstance::call_bound`]: crate::GuestInstance::call_bound
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+};
+
+use wasmer::{
+    AsStoreMut, AsStoreRef, Exports, Extern, Function, FunctionEnv, FunctionEnvMut, Imports,
+    Instance, Memory, Module, RuntimeError, Store, Type, TypedFunction,
+};
+use wasmer_types::ModuleInfo;
+use wasmparser::{ExternalKind, MemoryType, Parser, Payload, TypeRef};
+
+use crate::{
+    error::{Error, Result},
+    imports::is_wasi_namespace,
+    stdio::GuestStdout,
+    values::type_name,
+};
+
+wai_bindgen_wasmer::export!("wit/rust.wai");
+wai_bindgen_wasmer::export!("wit/integers.wai");
+
+/// The `strings` interface of `wit/strings.wai`, with the API `export!` would give it.
+pub mod strings {
+    use super::*;
+
+    pub trait Strings: Send + 'static {
+        fn a(&mut self, x: &str);
+        fn b(&mut self) -> String;
+        fn c(&mut self, a: &str, b: &str) -> String;
+    }
+
+    struct StringsEnv<T> {
+        host: T,
+        guest: Arc<OnceLock<Guest>>,
+    }
+
+    /// The guest's memory and allocator, known once the instance exists.
+    struct Guest {
+        memory: Memory,
+        realloc: TypedFunction<(i32, i32, i32, i32), i32>,
+    }
+
+    pub fn add_to_imports<T: Strings>(
+        store: &mut impl AsStoreMut,
+        imports: &mut Imports,
+        host: T,
+    ) -> impl FnOnce(&Instance, &dyn AsStoreRef) -> anyhow::Result<()> {
+        let guest = Arc::new(OnceLock::new());
+        let env = FunctionEnv::new(
+            store,
+            StringsEnv {
+                host,
+                guest: guest.clone(),
+            },
+        );
+        let mut exports = Exports::new();
+        exports.insert("a", Function::new_typed_with_env(store, &env, a::<T>));
+        exports.insert("b", Function::new_typed_with_env(store, &env, b::<T>));
+        exports.insert("c", Function::new_typed_with_env(store, &env, c::<T>));
+        imports.register_namespace("strings", exports);
+
+        move |instance, store| {
+            let memory = instance.exports.get_memory("memory")?.clone();
+            let realloc = instance
+                .exports
+                .get_typed_function(&store.as_store_ref(), "canonical_abi_realloc")?;
+            guest
+                .set(Guest { memory, realloc })
+                .map_err(|_| anyhow::anyhow!("the strings interface is already initialized"))
+        }
+    }
+
+    fn a<T: Strings>(
+        mut env: FunctionEnvMut<StringsEnv<T>>,
+        ptr: i32,
+        len: i32,
+    ) -> Result<(), RuntimeError> {
+        let x = read_string(&env, ptr, len)?;
+        env.data_mut().host.a(&x);
+        Ok(())
+    }
+
+    fn b<T: Strings>(mut env: FunctionEnvMut<StringsEnv<T>>, ret: i32) -> Result<(), RuntimeError> {
+        let result = env.data_mut().host.b();
+        return_string(&mut env, ret, &result)
+    }
+
+    fn c<T: Strings>(
+        mut env: FunctionEnvMut<StringsEnv<T>>,
+        a_ptr: i32,
+        a_len: i32,
+        b_ptr: i32,
+        b_len: i32,
+        ret: i32,
+    ) -> Result<(), RuntimeError> {
+        let a = read_string(&env, a_ptr, a_len)?;
+        let b = read_string(&env, b_ptr, b_len)?;
+        let result = env.data_mut().host.c(&a, &b);
+        return_string(&mut env, ret, &result)
+    }
+
+    fn guest<T: Strings>(
+        env: &FunctionEnvMut<StringsEnv<T>>,
+    ) -> Result<Arc<OnceLock<Guest>>, RuntimeError> {
+        let guest = env.data().guest.clone();
+        match guest.get() {
+            Some(_) => Ok(guest),
+            None => Err(RuntimeError::new(
+                "the strings interface was called before it was initialized",
+            )),
+        }
+    }
+
+    fn read_string<T: Strings>(
+        env: &FunctionEnvMut<StringsEnv<T>>,
+        ptr: i32,
+        len: i32,
+    ) -> Result<String, RuntimeError> {
+        let guest = guest(env)?;
+        let memory = &guest.get().unwrap().memory;
+        let mut bytes = vec![0; len as u32 as usize];
+        memory
+            .view(env)
+            .read(ptr as u32 as u64, &mut bytes)
+            .map_err(|err| RuntimeError::new(err.to_string()))?;
+        String::from_utf8(bytes).map_err(|err| RuntimeError::new(err.to_string()))
+    }
+
+    /// Copies `value` into memory allocated by the guest and stores its pointer and
+    /// length at `ret`, as the canonical ABI returns strings from imports.
+    fn return_string<T: Strings>(
+        env: &mut FunctionEnvMut<StringsEnv<T>>,
+        ret: i32,
+        value: &str,
+    ) -> Result<(), RuntimeError> {
+        let guest = guest(env)?;
+        let Guest { memory, realloc } = guest.get().unwrap();
+        let len = value.len() as i32;
+        let ptr = realloc.call(env, 0, 0, 1, len)?;
+
+        let view = memory.view(env);
+        let mut pair = [0; 8];
+        pair[..4].copy_from_slice(&ptr.to_le_bytes());
+        pair[4..].copy_from_slice(&len.to_le_bytes());
+        view.write(ptr as u32 as u64, value.as_bytes())
+            .and_then(|()| view.write(ret as u32 as u64, &pair))
+            .map_err(|err| RuntimeError::new(err.to_string()))
+    }
+}
+
+/// Runs once the instance exists, giving the generated glue the guest's memory and
+/// allocator.
+type Initializer = Box<dyn FnOnce(&Instance, &dyn AsStoreRef) -> anyhow::Result<()>>;
+
+type Binding = Box<dyn FnOnce(&mut Store, &mut Imports) -> Initializer>;
+
+/// Interfaces whose generated host side is linked into the guest.
+#[derive(Default)]
+pub struct WaiImports {
+    bindings: Vec<Binding>,
+}
+
+/// Generated imports matched to the guest, waiting for [`WaiLinked::initialize`].
+pub struct WaiLinked {
+    pub imports: Imports,
+    initializers: Vec<Initializer>,
+}
+
+impl WaiImports {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The interfaces of the bundled samples: `rust` for the cswasi guests, and
+    /// `integers` and `strings` for TestGenerated.wasm. They print to `stdout`.
+    pub fn with_samples(stdout: GuestStdout) -> Self {
+        let rust = RustHost {
+            stdout: stdout.clone(),
+        };
+        let sample = SampleHost { stdout };
+        let sample_strings = sample.clone();
+
+        let mut imports = Self::new();
+        imports
+            .add(|store, imports| rust::add_to_imports(store, imports, rust))
+            .add(|store, imports| integers::add_to_imports(store, imports, sample))
+            .add(|store, imports| strings::add_to_imports(store, imports, sample_strings));
+        imports
+    }
+
+    /// Adds an interface, given the `add_to_imports` of its generated module.
+    pub fn add<F, I>(&mut self, add_to_imports: F) -> &mut Self
+    where
+        F: FnOnce(&mut Store, &mut Imports) -> I + 'static,
+        I: FnOnce(&Instance, &dyn AsStoreRef) -> anyhow::Result<()> + 'static,
+    {
+        self.bindings.push(Box::new(move |store, imports| {
+            Box::new(add_to_imports(store, imports)) as Initializer
+        }));
+        self
+    }
+
+    /// Creates the generated functions and keeps those the module imports, under the
+    /// names the module uses for them. Interfaces the module doesn't use are dropped.
+    pub fn link(self, store: &mut Store, module: &ModuleInfo) -> WaiLinked {
+        let mut imports = Imports::new();
+        let mut initializers = Vec::new();
+        for binding in self.bindings {
+            let mut generated = Imports::new();
+            let initializer = binding(store, &mut generated);
+            let available: Vec<((String, String), Extern)> = (&generated).into_iter().collect();
+
+            let mut used = false;
+            for import in module.imports() {
+                if is_wasi_namespace(import.module()) {
+                    continue;
+                }
+                let wanted = (
+                    normalize(import.module()),
+                    normalize(interface_name(import.name())),
+                );
+                let found = available.iter().find(|((namespace, name), _)| {
+                    (normalize(namespace), normalize(name)) == wanted
+                });
+                if let Some((_, function)) = found {
+                    imports.define(import.module(), import.name(), function.clone());
+                    used = true;
+                }
+            }
+            if used {
+                initializers.push(initializer);
+            }
+        }
+
+        WaiLinked {
+            imports,
+            initializers,
+        }
+    }
+}
+
+impl WaiLinked {
+    /// Hands the instance's memory and allocator to the generated glue. `bytes` is
+    /// the module the instance was made from.
+    pub fn initialize(self, store: &mut Store, instance: &Instance, bytes: &[u8]) -> Result<()> {
+        let exports = guest_exports(store, instance, bytes)?;
+        let exports = exports.as_ref().unwrap_or(instance);
+        for initialize in self.initializers {
+            initialize(exports, &*store).map_err(|err| Error::Bindings(format!("{err:#}")))?;
+        }
+        Ok(())
+    }
+}
+
+/// Wraps guests built with the newer canonical ABI, which export `cabi_realloc`
+/// where the generated glue looks for `canonical_abi_realloc`.
+///
+/// The shim re-exports the guest's memories and functions under their own names and
+/// adds the alias, so the glue ends up holding the guest's exports under the names it
+/// expects. Memories keep the type `bytes` declares for them. Returns `None` when
+/// the guest needs no shim.
+pub(crate) fn guest_exports(
+    store: &mut Store,
+    instance: &Instance,
+    bytes: &[u8],
+) -> Result<Option<Instance>> {
+    let exports = &instance.exports;
+    if exports.get_function("canonical_abi_realloc").is_ok()
+        || exports.get_function("cabi_realloc").is_err()
+    {
+        return Ok(None);
+    }
+
+    let memories = exported_memories(bytes)?;
+    let mut wat = String::from("(module\n");
+    let mut imports = Imports::new();
+    for (index, (name, export)) in exports.iter().enumerate() {
+        let import = format!("e{index}");
+        let declaration = match export {
+            Extern::Function(function) => {
+                let ty = function.ty(store);
+                let params = wat_types("param", ty.params());
+                let results = wat_types("result", ty.results());
+                format!("func ${import} {params} {results}")
+            }
+            Extern::Memory(_) => match memories.get(name.as_str()) {
+                Some(ty) => format!("memory ${import} {}", wat_memory_type(ty)),
+                None => continue,
+            },
+            _ => continue,
+        };
+        let kind = declaration.split(' ').next().unwrap_or_default();
+        wat.push_str(&format!(
+            "  (import \"guest\" \"{import}\" ({declaration}))\n  (export {} ({kind} ${import}))\n",
+            wat_string(name)
+        ));
+        if name == "cabi_realloc" {
+            wat.push_str(&format!(
+                "  (export \"canonical_abi_realloc\" (func ${import}))\n"
+            ));
+        }
+        imports.define("guest", &import, export.clone());
+    }
+    wat.push(')');
+
+    let module = Module::new(store, wat)?;
+    Ok(Some(Instance::new(store, &module, &imports)?))
+}
+
+/// The types of the memories the module exports, by export name.
+fn exported_memories(bytes: &[u8]) -> Result<HashMap<&str, MemoryType>> {
+    let mut memories = Vec::new();
+    let mut exports = HashMap::new();
+    for payload in Parser::new(0).parse_all(bytes) {
+        match payload? {
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    if let TypeRef::Memory(ty) = import?.ty {
+                        memories.push(ty);
+                    }
+                }
+            }
+            Payload::MemorySection(reader) => {
+                for ty in reader {
+                    memories.push(ty?);
+                }
+            }
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    let export = export?;
+                    if export.kind == ExternalKind::Memory {
+                        exports.insert(export.name, export.index);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(exports
+        .into_iter()
+        .filter_map(|(name, index)| Some((name, *memories.get(index as usize)?)))
+        .collect())
+}
+
+fn wat_memory_type(ty: &MemoryType) -> String {
+    let mut wat = String::new();
+    if ty.memory64 {
+        wat.push_str("i64 ");
+    }
+    wat.push_str(&ty.initial.to_string());
+    if let Some(maximum) = ty.maximum {
+        wat.push_str(&format!(" {maximum}"));
+    }
+    if ty.shared {
+        wat.push_str(" shared");
+    }
+    wat
+}
+
+fn wat_types(keyword: &str, types: &[Type]) -> String {
+    if types.is_empty() {
+        return String::new();
+    }
+    let names: Vec<&str> = types.iter().map(|ty| type_name(*ty)).collect();
+    format!("({keyword} {})", names.join(" "))
+}
+
+/// Quotes an export name, escaping every byte that isn't plainly printable.
+fn wat_string(name: &str) -> String {
+    let mut quoted = String::from("\"");
+    for byte in name.bytes() {
+        match byte {
+            b'"' | b'\\' | ..=0x1f | 0x7f.. => quoted.push_str(&format!("\\{byte:02x}")),
+            _ => quoted.push(byte as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Older wit-bindgen guests import `name: func(...)` rather than `name`.
+fn interface_name(import: &str) -> &str {
+    import
+        .split_once(':')
+        .map_or(import, |(name, _)| name)
+        .trim()
+}
+
+/// Lets `wasmImportFloat32Param` and `Integers` match the generated
+/// `wasm-import-float32-param` and `integers`.
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, '-' | '_'))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// The `rust` module the C# samples declare with `WasmImportLinkage`.
+pub struct RustHost {
+    pub stdout: GuestStdout,
+}
+
+impl rust::Rust for RustHost {
+    fn wasm_import_float32_param(&mut self, x: f32) {
+        self.stdout.println(format_args!("Hello from rust {x:#?}"));
+    }
+
+    fn wasm_import_float32_result(&mut self) -> f32 {
+        self.stdout.println("Hello from rust, returning an f32");
+        1.0
+    }
+
+    fn wasm_import_float64_param(&mut self, x: f64) {
+        self.stdout.println(format_args!("Hello from rust {x:#?}"));
+    }
+
+    fn wasm_import_float64_result(&mut self) -> f64 {
+        self.stdout.println("Hello from rust, returning an f64");
+        1.0
+    }
+}
+
+/// The `integers` and `strings` interfaces of the wit-bindgen-csharp-test guest.
+#[derive(Clone)]
+pub struct SampleHost {
+    pub stdout: GuestStdout,
+}
+
+impl integers::Integers for SampleHost {
+    fn a6(&mut self, x: i32) {
+        self.stdout
+            .println(format_args!("Hello from rust, a6({x})"));
+    }
+}
+
+impl strings::Strings for SampleHost {
+    fn a(&mut self, x: &str) {
+        self.stdout
+            .println(format_args!("Hello from rust, a({x:?})"));
+    }
+
+    fn b(&mut self) -> String {
+        "Hello from rust".to_string()
+    }
+
+    fn c(&mut self, a: &str, b: &str) -> String {
+        format!("{a}{b}")
+    }
+}
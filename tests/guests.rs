@@ -0,0 +1,173 @@
+//! Runs every bundled guest module through the host binary and compares its exit
+//! status and output with the files in `tests/golden`.
+//!
+//! Set `UPDATE_GOLDENS=1` to write the golden files from the current output, e.g.
+//! after adding a module. Without it a missing golden file fails the test.
+//!
+//! The cswasi samples were built without `[WasmImportLinkage]` on
+//! `wasmImportFloat64Param`, so they trap in wasm-ld's `undefined_stub` once they
+//! call it, after the `rust` import that is linked has printed its greeting.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use cs_runtime_example::outcome::TRAP_EXIT_CODE;
+
+const UPDATE_ENV: &str = "UPDATE_GOLDENS";
+
+fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn run_guest(module: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_cs-runtime-example"))
+        .current_dir(root())
+        // The debug and release samples import `wasi.thread-spawn`, which
+        // wasmer-wasix doesn't provide.
+        .args([
+            "run",
+            "--no-cache",
+            "--insecure-allow-all",
+            "--stub-missing-imports",
+        ])
+        .arg(root().join(module))
+        // Keep eyre from appending backtraces to host errors.
+        .env_remove("RUST_BACKTRACE")
+        .env_remove("RUST_LIB_BACKTRACE")
+        .output()
+        .expect("failed to run the host binary")
+}
+
+fn normalize(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .replace("\r\n", "\n")
+        .replace(&root().display().to_string(), "<root>")
+}
+
+fn golden_path(module: &str, stream: &str) -> PathBuf {
+    let stem = module.trim_end_matches(".wasm");
+    root().join("tests/golden").join(format!("{stem}.{stream}"))
+}
+
+fn check_golden(module: &str, stream: &str, actual: &str) {
+    let path = golden_path(module, stream);
+    if std::env::var_os(UPDATE_ENV).is_some_and(|value| value == "1") {
+        fs::write(&path, actual).expect("failed to write the golden file");
+        eprintln!("Wrote {}", path.display());
+        return;
+    }
+
+    let expected = match fs::read_to_string(&path) {
+        Ok(expected) => expected.replace("\r\n", "\n"),
+        Err(err) => panic!(
+            "failed to read {}: {err}, run with {UPDATE_ENV}=1 to create it",
+            path.display()
+        ),
+    };
+    assert!(
+        expected == actual,
+        "{module} {stream} differs from {}, rerun with {UPDATE_ENV}=1 if the change is \
+         expected\n--- expected\n{expected}\n--- actual\n{actual}",
+        path.display()
+    );
+}
+
+fn check_guest(module: &str, expected_code: i32) -> (String, String) {
+    let output = run_guest(module);
+    let stdout = normalize(&output.stdout);
+    let stderr = normalize(&output.stderr);
+
+    assert_eq!(
+        output.status.code(),
+        Some(expected_code),
+        "unexpected exit status for {module}\n--- stdout\n{stdout}\n--- stderr\n{stderr}"
+    );
+    check_golden(module, "stdout", &stdout);
+    check_golden(module, "stderr", &stderr);
+    (stdout, stderr)
+}
+
+fn check_csharp_sample(module: &str) {
+    let (stdout, _) = check_guest(module, TRAP_EXIT_CODE);
+    assert!(
+        stdout.contains("Hello from rust"),
+        "{module} never called the `rust` host import"
+    );
+    assert!(
+        stdout.contains("at undefined_stub"),
+        "{module} trapped somewhere else than at its unlinked import"
+    );
+}
+
+#[test]
+fn cswasi() {
+    check_csharp_sample("cswasi.wasm");
+}
+
+#[test]
+fn cswasi_debug() {
+    check_csharp_sample("cswasi-debug.wasm");
+}
+
+#[test]
+fn cswasi_release() {
+    check_csharp_sample("cswasi-release.wasm");
+}
+
+#[test]
+fn test_generated() {
+    let (stdout, _) = check_guest("TestGenerated.wasm", 0);
+    assert!(
+        stdout.contains("Hello from rust, a6("),
+        "TestGenerated.wasm never called the `Integers` WAI import"
+    );
+}
+
+#[test]
+fn inspect_cswasi() {
+    let output = Command::new(env!("CARGO_BIN_EXE_cs-runtime-example"))
+        .args(["inspect", "--json"])
+        .arg(root().join("cswasi.wasm"))
+        .output()
+        .expect("failed to run the host binary");
+    assert!(output.status.success(), "inspect failed: {output:?}");
+
+    let report: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("inspect did not print JSON");
+    assert_eq!(report["toolchain"]["runtime"], "NativeAot");
+    assert!(report["imports"]["wasi_snapshot_preview1"].is_array());
+    assert!(report["imports"]["rust"].is_array());
+    assert_eq!(report["unsatisfied_imports"], serde_json::json!([]));
+}
+
+#[test]
+fn precompiled_cswasi() {
+    let artifact = std::env::temp_dir().join(format!("cswasi-{}.csguest", std::process::id()));
+    let output = Command::new(env!("CARGO_BIN_EXE_cs-runtime-example"))
+        .arg("compile")
+        .arg(root().join("cswasi.wasm"))
+        .arg("--output")
+        .arg(&artifact)
+        .output()
+        .expect("failed to run the host binary");
+    assert!(output.status.success(), "compile failed: {output:?}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cs-runtime-example"))
+        .args(["run", "--no-cache", "--insecure-allow-all", "--precompiled"])
+        .arg(&artifact)
+        .output()
+        .expect("failed to run the host binary");
+    let _ = fs::remove_file(&artifact);
+
+    let stderr = normalize(&output.stderr);
+    assert_eq!(
+        output.status.code(),
+        Some(TRAP_EXIT_CODE),
+        "the artifact ran differently than the module:\n{stderr}"
+    );
+    assert!(stderr.contains("Loaded precompiled module"), "{stderr}");
+    assert!(normalize(&output.stdout).contains("Hello from rust"));
+}